@@ -0,0 +1,43 @@
+#[macro_use]
+extern crate criterion;
+extern crate rayon;
+extern crate rayon_adaptive;
+
+use rayon_adaptive::{prelude::*, Policy};
+
+use criterion::{Criterion, ParameterizedBenchmark};
+const INPUT_SIZE: usize = 100_000;
+
+/// Fine grained folds produce lots of outputs.
+/// We measure the cost of gathering them when iterating on the results.
+fn gather_outputs(c: &mut Criterion) {
+    let block_sizes = vec![10, 50, 100, 500, 1000];
+    let pool = rayon::ThreadPoolBuilder::new()
+        .build()
+        .expect("pool build failed");
+    c.bench(
+        "gather outputs",
+        ParameterizedBenchmark::new(
+            "join",
+            move |b, block_size| {
+                b.iter(|| {
+                    pool.install(|| {
+                        assert_eq!(
+                            (0..INPUT_SIZE)
+                                .into_adapt_iter()
+                                .with_policy(Policy::Join(*block_size))
+                                .fold(|| 0, |s, e| s + e)
+                                .into_iter()
+                                .sum::<usize>(),
+                            INPUT_SIZE * (INPUT_SIZE - 1) / 2
+                        );
+                    });
+                })
+            },
+            block_sizes,
+        ),
+    );
+}
+
+criterion_group!(benches, gather_outputs);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+#[macro_use]
+extern crate criterion;
+extern crate rayon;
+extern crate rayon_adaptive;
+
+use rayon_adaptive::{prelude::*, Policy};
+use std::iter::repeat;
+
+use criterion::{Criterion, ParameterizedBenchmark};
+const INPUT_SIZE: usize = 1_000_000;
+
+// we run on pools with more threads than cores.
+// waiting threads should not steal cpu time from working ones.
+fn oversubscribed(c: &mut Criterion) {
+    let cores = rayon::current_num_threads();
+    let factors = vec![1, 2, 4, 8];
+    c.bench(
+        "oversubscribed pools",
+        ParameterizedBenchmark::new(
+            "adaptive max",
+            move |b, factor| {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(cores * *factor)
+                    .build()
+                    .expect("pool build failed");
+                b.iter(|| {
+                    pool.install(|| {
+                        assert_eq!(
+                            (0..INPUT_SIZE)
+                                .into_adapt_iter()
+                                .with_policy(Policy::Adaptive(1000, 50_000))
+                                .max(),
+                            Some(INPUT_SIZE - 1)
+                        );
+                    })
+                })
+            },
+            factors,
+        )
+        .with_function("helping fold", move |b, factor| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(cores * *factor)
+                .build()
+                .expect("pool build failed");
+            b.iter(|| {
+                pool.install(|| {
+                    assert_eq!(
+                        (0..INPUT_SIZE)
+                            .into_adapt_iter()
+                            .by_blocks(repeat(INPUT_SIZE / 10))
+                            .fold(|| 0, |s, e| s + e)
+                            .helping_fold(0, |s, e| s + e, |s1, s2| s1 + s2),
+                        INPUT_SIZE * (INPUT_SIZE - 1) / 2
+                    );
+                })
+            })
+        }),
+    );
+}
+
+criterion_group!(benches, oversubscribed);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+#[macro_use]
+extern crate criterion;
+extern crate rayon;
+extern crate rayon_adaptive;
+
+use rayon_adaptive::{prelude::*, Policy};
+use std::iter::repeat;
+
+use criterion::{Criterion, ParameterizedBenchmark};
+const INPUT_SIZE: usize = 100_000;
+const THREADS: usize = 4;
+
+// tiny blocks on several threads : we steal all the time.
+// we measure the cost of each steal handshake.
+fn steal_handshakes(c: &mut Criterion) {
+    let block_sizes = vec![10, 50, 100, 500, 1000];
+    let pool = std::sync::Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(THREADS)
+            .build()
+            .expect("pool build failed"),
+    );
+    let helping_pool = pool.clone();
+    c.bench(
+        "steal handshakes",
+        ParameterizedBenchmark::new(
+            "adaptive max",
+            move |b, block_size| {
+                b.iter(|| {
+                    pool.install(|| {
+                        assert_eq!(
+                            (0..INPUT_SIZE)
+                                .into_adapt_iter()
+                                .with_policy(Policy::Adaptive(*block_size, *block_size))
+                                .max(),
+                            Some(INPUT_SIZE - 1)
+                        );
+                    });
+                })
+            },
+            block_sizes,
+        )
+        .with_function("helping fold", move |b, block_size| {
+            b.iter(|| {
+                helping_pool.install(|| {
+                    assert_eq!(
+                        (0..INPUT_SIZE)
+                            .into_adapt_iter()
+                            .with_policy(Policy::Adaptive(*block_size, *block_size))
+                            .by_blocks(repeat(INPUT_SIZE / 10))
+                            .fold(|| 0, |s, e| s + e)
+                            .helping_fold(0, |s, e| s + e, |s1, s2| s1 + s2),
+                        INPUT_SIZE * (INPUT_SIZE - 1) / 2
+                    );
+                })
+            })
+        }),
+    );
+}
+
+criterion_group!(benches, steal_handshakes);
+criterion_main!(benches);
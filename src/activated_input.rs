@@ -1,68 +1,119 @@
 //! the folded stuff, ready to be reduced.
-use crate::folders::Map;
+use crate::folders::{Map, Pipe, Progress};
+use crate::outputs::{concatenate, gathering, GatheringFolder, OutputsIter};
 use crate::prelude::*;
-use crate::scheduling::{fold_with_help, schedule};
+use crate::scheduling::{fold_with_divide_help, fold_with_help, schedule, SchedulingParameters};
+use crate::stream::OrderedStream;
 use crate::traits::{BasicPower, BlockedOrMore};
-use crate::{DivisibleIntoBlocks, Folder, Policy};
+use crate::{max_block_size, DivisibleIntoBlocks, Folder, Partial, Policy, Timed};
+use rayon_core::ThreadPool;
 use std::cmp::min;
-use std::collections::linked_list;
-use std::collections::linked_list::LinkedList;
 use std::iter::{once, Chain, Empty, Once};
 use std::marker::PhantomData;
 
 /// Lazily store everything for folding.
-pub struct ActivatedInput<F: Folder, S, P> {
-    pub(crate) input: F::Input,       // what we fold
-    pub(crate) folder: F,             // how we fold it
-    pub(crate) policy: Policy,        // with what scheduler
-    pub(crate) sizes: S,              // blocks sizes iterator (if any)
-    pub(crate) power: PhantomData<P>, // what can we do
+pub struct ActivatedInput<'p, F: Folder, S, P> {
+    pub(crate) input: F::Input,                 // what we fold
+    pub(crate) folder: F,                       // how we fold it
+    pub(crate) policy: Policy,                  // with what scheduler
+    pub(crate) sizes: S,                        // blocks sizes iterator (if any)
+    pub(crate) max_threads: Option<usize>,      // how many threads can work on it
+    pub(crate) pool: Option<&'p ThreadPool>,    // where it runs (the current pool if none)
+    pub(crate) cache_block_size: Option<usize>, // largest block fitting in a core's cache
+    pub(crate) power: PhantomData<P>,           // what can we do
 }
 
-impl<F: Folder> IntoIterator for ActivatedInput<F, Empty<usize>, BasicPower>
+impl<'p, F: Folder> IntoIterator for ActivatedInput<'p, F, Empty<usize>, BasicPower>
 where
     F: Folder,
     F::Input: Divisible<Power = BasicPower>,
 {
     type Item = F::Output;
-    type IntoIter = linked_list::IntoIter<F::Output>;
+    type IntoIter = OutputsIter<F::Output>;
     fn into_iter(self) -> Self::IntoIter {
-        let (input, folder, policy) = (self.input, self.folder, self.policy);
-        let list_folder = folder.map(|o| {
-            let mut l = LinkedList::new();
-            l.push_back(o);
-            l
-        });
-
-        let outputs_list = schedule(
-            input,
-            &list_folder,
-            &|mut left, mut right| {
-                left.append(&mut right);
-                left
-            },
-            policy,
+        let parameters = self.parameters();
+        let outputs = schedule(
+            self.input,
+            &gathering(self.folder),
+            &concatenate,
+            parameters,
         );
-        outputs_list.into_iter()
+        outputs.into_iter()
     }
 }
 
-impl<F: Folder, S, P> ActivatedInput<F, S, P> {
+impl<'p, F: Folder, S, P> ActivatedInput<'p, F, S, P> {
     pub fn map<O: Send + Sync, M: Fn(F::Output) -> O + Sync>(
         self,
         map_op: M,
-    ) -> ActivatedInput<Map<F, O, M>, S, P> {
+    ) -> ActivatedInput<'p, Map<F, O, M>, S, P> {
         ActivatedInput {
             input: self.input,
             folder: self.folder.map(map_op),
             policy: self.policy,
             sizes: self.sizes,
+            max_threads: self.max_threads,
+            pool: self.pool,
+            cache_block_size: self.cache_block_size,
+            power: self.power,
+        }
+    }
+    /// Call given callback with the number of processed elements (in `base_length` units)
+    /// and the initial input's length as the computation advances.
+    /// Calls are rate-limited and can come from any thread.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// let reported = AtomicUsize::new(0);
+    /// let sum = (0..100_000)
+    ///     .into_adapt_iter()
+    ///     .fold(|| 0, |s, e| s + e)
+    ///     .with_progress(|done, total| {
+    ///         assert!(done <= total);
+    ///         reported.store(done, Ordering::SeqCst)
+    ///     })
+    ///     .reduce(|s1, s2| s1 + s2);
+    /// assert_eq!(sum, 4_999_950_000);
+    /// assert_eq!(reported.load(Ordering::SeqCst), 100_000);
+    /// ```
+    pub fn with_progress<C: Fn(usize, usize) + Sync>(
+        self,
+        callback: C,
+    ) -> ActivatedInput<'p, Progress<F, C>, S, P> {
+        let total = self.input.base_length();
+        ActivatedInput {
+            input: self.input,
+            folder: Progress::new(self.folder, callback, total),
+            policy: self.policy,
+            sizes: self.sizes,
+            max_threads: self.max_threads,
+            pool: self.pool,
+            cache_block_size: self.cache_block_size,
             power: self.power,
         }
     }
+    /// Cap default block sizes so that blocks of `T` fit in a core's cache.
+    pub(crate) fn fitting_in_cache<T>(self) -> Self {
+        ActivatedInput {
+            cache_block_size: Some(max_block_size::<T>()),
+            ..self
+        }
+    }
+    /// How to schedule us.
+    pub(crate) fn parameters(&self) -> SchedulingParameters<'p> {
+        SchedulingParameters {
+            policy: self.policy,
+            max_threads: self.max_threads,
+            cache_block_size: self.cache_block_size,
+            pool: self.pool,
+        }
+    }
 }
 
-impl<F> ActivatedInput<F, Empty<usize>, BasicPower>
+impl<'p, F> ActivatedInput<'p, F, Empty<usize>, BasicPower>
 where
     F: Folder,
     F::Input: Divisible<Power = BasicPower>,
@@ -71,12 +122,50 @@ where
         self,
         reduce_function: RF,
     ) -> F::Output {
-        let (input, folder, policy) = (self.input, self.folder, self.policy);
-        schedule(input, &folder, &reduce_function, policy)
+        let parameters = self.parameters();
+        schedule(self.input, &self.folder, &reduce_function, parameters)
+    }
+
+    /// Reduce and tell which parts of the input were processed before the deadline.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// use std::time::Instant;
+    /// let partial = (0..1_000)
+    ///     .with_deadline(Instant::now())
+    ///     .partial_fold(|| 0, |s, r, limit| (s + limit, r.divide_at(limit).1))
+    ///     .reduce_until_deadline(|s1, s2| s1 + s2);
+    /// assert_eq!(partial.value, 0);
+    /// assert!(partial.processed_ranges.is_empty());
+    /// ```
+    pub fn reduce_until_deadline<I, RF>(self, reduce_function: RF) -> Partial<F::Output>
+    where
+        F: Folder<Input = Timed<I>>,
+        I: Divisible,
+        RF: Fn(F::Output, F::Output) -> F::Output + Sync,
+    {
+        let tracker = self.input.tracker.clone();
+        let value = self.reduce(reduce_function);
+        tracker.partial(value)
+    }
+
+    /// Fold sequentially with `f` while idle threads help us with our folder.
+    /// Since we cannot cut blocks, helpers only split the input in halves.
+    pub fn helping_partial_fold<B, FOLD, RET>(self, init: B, f: FOLD, retrieve: RET) -> B
+    where
+        F: Send,
+        B: Send,
+        FOLD: Fn(B, F::Input, usize) -> (B, F::Input) + Sync,
+        RET: Fn(B, F::Output) -> B + Sync,
+    {
+        let parameters = self.parameters();
+        fold_with_divide_help(self.input, init, f, self.folder, retrieve, parameters)
     }
 }
 
-impl<F, S> ActivatedInput<F, S, BlockedOrMore>
+impl<'p, F, S> ActivatedInput<'p, F, S, BlockedOrMore>
 where
     F: Folder,
     F::Input: DivisibleIntoBlocks,
@@ -86,45 +175,171 @@ where
         self,
         reduce_function: RF,
     ) -> F::Output {
-        let (input, folder, policy, sizes) = (self.input, self.folder, self.policy, self.sizes);
+        let parameters = self.parameters();
+        let (input, folder, sizes) = (self.input, self.folder, self.sizes);
         let reduce_ref = &reduce_function;
         let length = input.base_length();
+        if length == 0 {
+            // nothing to do (maybe we got cancelled before starting)
+            return folder.to_output(folder.identity(), input);
+        }
         let mut outputs = input
             .chunks(sizes.chain(once(length)))
-            .map(|input| schedule(input, &folder, reduce_ref, policy));
+            .map(|input| schedule(input, &folder, reduce_ref, parameters));
         let first_output = outputs.next().unwrap();
         outputs.fold(first_output, reduce_ref)
     }
+
+    /// Reduce and tell which parts of the input were processed before the deadline.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// use std::time::{Duration, Instant};
+    /// let partial = (0..100_000)
+    ///     .into_adapt_iter()
+    ///     .with_deadline(Instant::now() + Duration::from_secs(3600))
+    ///     .fold(|| 0, |s, e| s + e)
+    ///     .reduce_until_deadline(|s1, s2| s1 + s2);
+    /// assert_eq!(partial.value, 4_999_950_000);
+    /// assert_eq!(partial.processed_ranges, vec![0..100_000]);
+    /// ```
+    pub fn reduce_until_deadline<I, RF>(self, reduce_function: RF) -> Partial<F::Output>
+    where
+        F: Folder<Input = Timed<I>>,
+        I: DivisibleIntoBlocks,
+        RF: Fn(F::Output, F::Output) -> F::Output + Sync,
+    {
+        let tracker = self.input.tracker.clone();
+        let value = self.reduce(reduce_function);
+        tracker.partial(value)
+    }
+}
+
+impl<F, S> ActivatedInput<'static, F, S, BlockedOrMore>
+where
+    F: Folder + Send + 'static,
+    F::Input: DivisibleIntoBlocks + 'static,
+    F::Output: 'static,
+    S: Iterator<Item = usize>,
+{
+    /// Iterate on all outputs in order while computing up to `lookahead` blocks ahead.
+    /// Blocks are given by `by_blocks` and computed in background tasks so
+    /// don't consume the stream from inside the thread pool.
+    /// Dropping the stream waits for blocks still being computed.
+    /// See `scoped_ordered_stream` for streaming borrowed inputs.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// use std::iter::repeat;
+    /// let v: Vec<usize> = (0..10_000)
+    ///     .into_adapt_iter()
+    ///     .by_blocks(repeat(1_000))
+    ///     .fold(Vec::new, |mut v, e| {
+    ///         v.push(e);
+    ///         v
+    ///     })
+    ///     .ordered_stream(2)
+    ///     .flatten()
+    ///     .collect();
+    /// assert_eq!(v, (0..10_000).collect::<Vec<usize>>());
+    /// ```
+    pub fn ordered_stream(
+        self,
+        lookahead: usize,
+    ) -> OrderedStream<'static, F, Chain<S, Once<usize>>> {
+        self.into_ordered_stream(lookahead)
+    }
 }
 
-pub struct OutputIterator<F: Folder, S> {
+impl<'p, F, S> ActivatedInput<'p, F, S, BlockedOrMore>
+where
+    F: Folder + Send,
+    F::Input: DivisibleIntoBlocks,
+    S: Iterator<Item = usize>,
+{
+    fn into_ordered_stream<'a>(
+        self,
+        lookahead: usize,
+    ) -> OrderedStream<'a, F, Chain<S, Once<usize>>>
+    where
+        'p: 'a,
+        F: 'a,
+        F::Input: 'a,
+        F::Output: 'a,
+    {
+        let length = self.input.base_length();
+        let parameters = self.parameters();
+        OrderedStream::new(
+            self.input,
+            self.folder,
+            parameters,
+            self.sizes.chain(once(length)),
+            lookahead,
+        )
+    }
+
+    /// Like `ordered_stream` but inputs and folders can borrow data.
+    /// The stream is only available inside `op` and we return once all blocks
+    /// computed ahead are done.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// use std::iter::repeat;
+    /// let v: Vec<u32> = (0..10_000).collect();
+    /// let doubled: Vec<u32> = v
+    ///     .as_slice()
+    ///     .into_adapt_iter()
+    ///     .by_blocks(repeat(1_000))
+    ///     .fold(Vec::new, |mut d, e| {
+    ///         d.push(2 * e);
+    ///         d
+    ///     })
+    ///     .scoped_ordered_stream(2, |stream| stream.flatten().collect());
+    /// assert_eq!(doubled, (0..10_000).map(|e| 2 * e).collect::<Vec<u32>>());
+    /// ```
+    pub fn scoped_ordered_stream<'a, R, OP>(self, lookahead: usize, op: OP) -> R
+    where
+        'p: 'a,
+        F: 'a,
+        F::Input: 'a,
+        F::Output: 'a,
+        OP: FnOnce(&mut OrderedStream<'a, F, Chain<S, Once<usize>>>) -> R,
+    {
+        // we keep the stream (dropped even if op panics) so it cannot outlive us
+        let mut stream = self.into_ordered_stream(lookahead);
+        op(&mut stream)
+    }
+}
+
+pub struct OutputIterator<'p, F: Folder, S> {
     remaining_input: F::Input,
-    folder: Map<F, LinkedList<F::Output>, fn(F::Output) -> LinkedList<F::Output>>,
+    folder: GatheringFolder<F>,
     sizes: S,
-    policy: Policy,
-    block_iterator: Option<linked_list::IntoIter<F::Output>>,
+    parameters: SchedulingParameters<'p>,
+    block_iterator: Option<OutputsIter<F::Output>>,
 }
 
-impl<F: Folder, S: Iterator<Item = usize>> OutputIterator<F, Chain<S, Once<usize>>> {
-    fn new(input: F::Input, folder: F, policy: Policy, sizes: S) -> Self {
-        fn into_list<T>(x: T) -> LinkedList<T> {
-            let mut l = LinkedList::new();
-            l.push_back(x);
-            l
-        }
+impl<'p, F: Folder, S: Iterator<Item = usize>> OutputIterator<'p, F, Chain<S, Once<usize>>> {
+    fn new(input: F::Input, folder: F, parameters: SchedulingParameters<'p>, sizes: S) -> Self {
         let length = input.base_length();
 
         OutputIterator {
             remaining_input: input,
-            folder: folder.map(into_list),
+            folder: gathering(folder),
             sizes: sizes.chain(once(length)),
-            policy,
+            parameters,
             block_iterator: None,
         }
     }
 }
 
-impl<F, S> Iterator for OutputIterator<F, S>
+impl<'p, F, S> Iterator for OutputIterator<'p, F, S>
 where
     F: Folder,
     F::Input: DivisibleIntoBlocks,
@@ -146,32 +361,24 @@ where
                 self.remaining_input.base_length(),
             );
             let next_chunk = self.remaining_input.cut_left_at(next_size);
-            let outputs_list = schedule(
-                next_chunk,
-                &self.folder,
-                &|mut left, mut right| {
-                    left.append(&mut right);
-                    left
-                },
-                self.policy,
-            );
-            self.block_iterator = Some(outputs_list.into_iter());
+            let outputs = schedule(next_chunk, &self.folder, &concatenate, self.parameters);
+            self.block_iterator = Some(outputs.into_iter());
             self.block_iterator.as_mut().unwrap().next()
         }
     }
 }
 
-impl<F, S> IntoIterator for ActivatedInput<F, S, BlockedOrMore>
+impl<'p, F, S> IntoIterator for ActivatedInput<'p, F, S, BlockedOrMore>
 where
     F: Folder,
     F::Input: DivisibleIntoBlocks,
     S: Iterator<Item = usize>,
 {
     type Item = F::Output;
-    type IntoIter = OutputIterator<F, Chain<S, Once<usize>>>;
+    type IntoIter = OutputIterator<'p, F, Chain<S, Once<usize>>>;
     fn into_iter(self) -> Self::IntoIter {
-        let (input, folder, policy, sizes) = (self.input, self.folder, self.policy, self.sizes);
-        OutputIterator::new(input, folder, policy, sizes)
+        let parameters = self.parameters();
+        OutputIterator::new(self.input, self.folder, parameters, self.sizes)
     }
 }
 
@@ -202,17 +409,19 @@ where
 //    }
 
 impl<
+        'p,
         I: AdaptiveIterator + DivisibleIntoBlocks,
         F: Folder<Input = I> + Send,
         S: Iterator<Item = usize> + Send,
-    > ActivatedInput<F, S, BlockedOrMore>
+    > ActivatedInput<'p, F, S, BlockedOrMore>
 {
     pub fn helping_for_each<FOREACH, RET>(self, f: FOREACH, retrieve: RET)
     where
         FOREACH: Fn(I::Item) + Sync,
         RET: Fn(F::Output) + Sync,
     {
-        let (input, folder, sizes, policy) = (self.input, self.folder, self.sizes, self.policy);
+        let parameters = self.parameters();
+        let (input, folder, sizes) = (self.input, self.folder, self.sizes);
         let f_ref = &f;
         let master_fold = |_: (), i: I, size: usize| -> ((), I) {
             let (todo, remaining) = i.divide_at(size);
@@ -224,16 +433,16 @@ impl<
             input,
             (),
             master_fold,
-            &folder,
+            folder,
             master_retrieve,
             sizes,
-            policy,
+            parameters,
         )
     }
 }
 
-impl<I: DivisibleIntoBlocks, F: Folder<Input = I> + Send, S: Iterator<Item = usize> + Send>
-    ActivatedInput<F, S, BlockedOrMore>
+impl<'p, I: DivisibleIntoBlocks, F: Folder<Input = I> + Send, S: Iterator<Item = usize> + Send>
+    ActivatedInput<'p, F, S, BlockedOrMore>
 {
     pub fn helping_partial_fold<B, FOLD, RET>(self, init: B, f: FOLD, retrieve: RET) -> B
     where
@@ -241,8 +450,63 @@ impl<I: DivisibleIntoBlocks, F: Folder<Input = I> + Send, S: Iterator<Item = usi
         FOLD: Fn(B, I, usize) -> (B, I) + Sync,
         RET: Fn(B, F::Output) -> B + Sync,
     {
-        let (input, folder, sizes, policy) = (self.input, self.folder, self.sizes, self.policy);
-        fold_with_help(input, init, f, &folder, retrieve, sizes, policy)
+        let parameters = self.parameters();
+        let (input, folder, sizes) = (self.input, self.folder, self.sizes);
+        fold_with_help(input, init, f, folder, retrieve, sizes, parameters)
+    }
+
+    /// Fuse with a second stage consuming our outputs, without any intermediate collect.
+    /// Each block folded by our folder goes straight into `stage2` on the same thread.
+    /// Idle threads steal parts of the input and run both stages on them, so outputs
+    /// reach `stage2` concurrently and in no particular order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// use std::iter::repeat;
+    /// use std::sync::Mutex;
+    /// let counts = Mutex::new(vec![0; 10]);
+    /// (0..100_000)
+    ///     .into_adapt_iter()
+    ///     .by_blocks(repeat(10_000))
+    ///     .fold(Vec::new, |mut v, e| {
+    ///         v.push(e % 10);
+    ///         v
+    ///     })
+    ///     .pipe(|digits| {
+    ///         let mut counts = counts.lock().unwrap();
+    ///         for digit in digits {
+    ///             counts[digit] += 1;
+    ///         }
+    ///     });
+    /// assert_eq!(counts.into_inner().unwrap(), vec![10_000; 10]);
+    /// ```
+    pub fn pipe<STAGE2>(self, stage2: STAGE2)
+    where
+        STAGE2: Fn(F::Output) + Sync,
+    {
+        let parameters = self.parameters();
+        let (input, folder, sizes) = (self.input, self.folder, self.sizes);
+        let (folder, stage2) = (&folder, &stage2);
+        let master_pipe = Pipe {
+            inner_folder: folder,
+            stage2,
+        };
+        let master_fold = |_: (), i: I, size: usize| master_pipe.fold((), i, size);
+        let slaves_pipe = Pipe {
+            inner_folder: folder,
+            stage2,
+        };
+        fold_with_help(
+            input,
+            (),
+            master_fold,
+            slaves_pipe,
+            |_, _| (),
+            sizes,
+            parameters,
+        )
     }
 
     pub fn helping_cutting_fold<B, FOLD, RET>(self, init: B, f: FOLD, retrieve: RET) -> B
@@ -251,17 +515,26 @@ impl<I: DivisibleIntoBlocks, F: Folder<Input = I> + Send, S: Iterator<Item = usi
         FOLD: Fn(B, I) -> B + Sync,
         RET: Fn(B, F::Output) -> B + Sync,
     {
-        let (input, folder, sizes, policy) = (self.input, self.folder, self.sizes, self.policy);
+        let parameters = self.parameters();
+        let (input, folder, sizes) = (self.input, self.folder, self.sizes);
         let cutting_fold = |io, i: I, limit| {
             let (todo, remaining) = i.divide_at(limit);
             (f(io, todo), remaining)
         };
-        fold_with_help(input, init, cutting_fold, &folder, retrieve, sizes, policy)
+        fold_with_help(
+            input,
+            init,
+            cutting_fold,
+            folder,
+            retrieve,
+            sizes,
+            parameters,
+        )
     }
 }
 
-impl<I: AdaptiveIterator, F: Folder<Input = I> + Send, S: Iterator<Item = usize> + Send>
-    ActivatedInput<F, S, BlockedOrMore>
+impl<'p, I: AdaptiveIterator, F: Folder<Input = I> + Send, S: Iterator<Item = usize> + Send>
+    ActivatedInput<'p, F, S, BlockedOrMore>
 {
     pub fn helping_fold<B, FOLD, RET>(self, init: B, f: FOLD, retrieve: RET) -> B
     where
@@ -269,7 +542,8 @@ impl<I: AdaptiveIterator, F: Folder<Input = I> + Send, S: Iterator<Item = usize>
         FOLD: Fn(B, I::Item) -> B + Sync,
         RET: Fn(B, F::Output) -> B + Sync,
     {
-        let (input, folder, sizes, policy) = (self.input, self.folder, self.sizes, self.policy);
+        let parameters = self.parameters();
+        let (input, folder, sizes) = (self.input, self.folder, self.sizes);
         let f_ref = &f;
         let sequential_fold = |io, i: I, limit| {
             let (todo, remaining) = i.divide_at(limit);
@@ -279,10 +553,10 @@ impl<I: AdaptiveIterator, F: Folder<Input = I> + Send, S: Iterator<Item = usize>
             input,
             init,
             sequential_fold,
-            &folder,
+            folder,
             retrieve,
             sizes,
-            policy,
+            parameters,
         )
     }
 }
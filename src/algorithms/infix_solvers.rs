@@ -71,7 +71,7 @@ pub fn vec_gen(size: u64) -> Vec<Token> {
                 Token::Num(num % 5)
             } else {
                 let temp: u32 = rand::random();
-                if temp % 100_000 == 0 {
+                if temp.is_multiple_of(100_000) {
                     Token::Add
                 } else {
                     Token::Mult
@@ -1,4 +1,5 @@
 //! adaptive parallel merge sort.
+use crate::backend;
 use crate::prelude::*;
 use crate::traits::{BasicPower, BlockedPower};
 use crate::{fuse_slices, Policy};
@@ -352,7 +353,7 @@ pub fn adaptive_sort_raw<T: Ord + Copy + Send + Sync>(slice: &mut [T]) {
     }
 
     let slice_len = slice.len();
-    let num_threads = rayon::current_num_threads();
+    let num_threads = backend::current_num_threads();
 
     let slices = SortingSlices {
         s: vec![slice, tmp_slice1.as_mut_slice(), tmp_slice2.as_mut_slice()],
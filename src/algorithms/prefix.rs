@@ -1,7 +1,7 @@
 //! Adaptive prefix algorithm.
 //! No macro blocks.
+use crate::backend;
 use crate::{prelude::*, BlockedPower, EdibleSliceMut};
-use rayon::scope;
 use std::iter::repeat;
 
 /// Run adaptive prefix algortihm on given slice.
@@ -117,7 +117,7 @@ where
         index: 0,
     };
 
-    scope(|s| {
+    backend::scope(|s| {
         input
             .by_blocks(repeat(length / 10))
             .work(|mut prefix_slice, limit| {
@@ -159,7 +159,7 @@ where
                 },
                 |last_num, slice| {
                     if let Some(last_slice_num) = slice.last().cloned() {
-                        s.spawn(move |_| {
+                        backend::spawn_in(s, move |_| {
                             slice.into_adapt_iter().for_each(|e| *e = op(&last_num, e))
                         });
                         op(&last_num, &last_slice_num)
@@ -1,3 +1,5 @@
+//! List of stolen tasks for the fully adaptive scheduler.
+use crate::signal::Signal;
 use crossbeam::atomic::AtomicCell;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -7,6 +9,7 @@ pub struct AtomicNode<T> {
     request: AtomicBool,
     content: AtomicCell<Option<T>>,
     next: AtomicCell<Option<AtomicLink<T>>>,
+    completed: Signal,
 }
 impl<T> AtomicNode<T> {
     pub fn new(content: T) -> Self {
@@ -14,6 +17,7 @@ impl<T> AtomicNode<T> {
             request: AtomicBool::new(false),
             content: AtomicCell::new(Some(content)),
             next: AtomicCell::new(None),
+            completed: Signal::new(),
         }
     }
 
@@ -38,6 +42,33 @@ impl<T> AtomicNode<T> {
     }
 }
 
+/// Thief side of a node once stolen.
+/// Dropping it wakes up anyone waiting to retrieve the node, even if we unwind
+/// (the retrieved content is then `None`).
+pub struct Completion<T> {
+    node: AtomicLink<T>,
+}
+
+impl<T> Completion<T> {
+    pub fn new(node: AtomicLink<T>) -> Self {
+        Completion { node }
+    }
+    pub fn node(&self) -> &AtomicLink<T> {
+        &self.node
+    }
+    /// Store final content and wake up anyone waiting to retrieve it.
+    pub fn complete(self, final_content: T) {
+        self.node.replace(final_content);
+        // dropping self now wakes up the retriever
+    }
+}
+
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        self.node.completed.notify()
+    }
+}
+
 #[derive(Default)]
 pub struct AtomicList<T> {
     head: AtomicCell<Option<AtomicLink<T>>>,
@@ -46,31 +77,18 @@ pub struct AtomicList<T> {
 impl<'a, T> Iterator for AtomicListIterator<'a, T> {
     type Item = T;
     fn next(&mut self) -> Option<Self::Item> {
-        let possible_head = self.list.head.swap(None);
-        if possible_head.is_some() {
-            let mut head = possible_head.unwrap();
+        if let Some(head) = self.list.head.swap(None) {
             head.request.store(true, Ordering::SeqCst);
-            let node: AtomicNode<T>;
             #[cfg(feature = "logs")]
             {
-                node = rayon_logs::subgraph("wait retrieving", 1, || loop {
-                    match Arc::try_unwrap(head) {
-                        Ok(real_node) => break real_node,
-                        Err(failed) => head = failed,
-                    }
-                })
+                rayon_logs::subgraph("wait retrieving", 1, || head.completed.wait())
             }
             #[cfg(not(feature = "logs"))]
             {
-                node = loop {
-                    match Arc::try_unwrap(head) {
-                        Ok(real_node) => break real_node,
-                        Err(failed) => head = failed,
-                    }
-                }
+                head.completed.wait()
             }
-            self.list.head.swap(node.next.swap(None));
-            node.take()
+            self.list.head.swap(head.next.swap(None));
+            head.take()
         } else {
             None
         }
@@ -87,7 +105,7 @@ impl<T> AtomicList<T> {
             head: AtomicCell::new(None),
         }
     }
-    pub fn iter(&self) -> AtomicListIterator<T> {
+    pub fn iter(&self) -> AtomicListIterator<'_, T> {
         AtomicListIterator { list: self }
     }
     pub fn push_front(&self, content: T) -> AtomicLink<T> {
@@ -98,3 +116,53 @@ impl<T> AtomicList<T> {
         new_node
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::Policy;
+    use std::iter::repeat;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn panicking_helpers_wake_up_the_master() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .expect("pool creation failed");
+        for _ in 0..10 {
+            let helped = AtomicBool::new(false);
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                pool.install(|| {
+                    (0..100_000)
+                        .into_adapt_iter()
+                        .with_policy(Policy::DefaultPolicy)
+                        .by_blocks(repeat(10_000))
+                        .fold(
+                            || 0,
+                            |_, _| {
+                                helped.store(true, Ordering::SeqCst);
+                                panic!("helper failing on purpose")
+                            },
+                        )
+                        .helping_fold(
+                            0,
+                            |s, e| {
+                                thread::sleep(Duration::from_micros(1));
+                                s + e
+                            },
+                            |s1, s2| s1 + s2,
+                        )
+                })
+            }));
+            if helped.load(Ordering::SeqCst) {
+                assert!(result.is_err());
+            } else {
+                assert_eq!(result.ok(), Some(4_999_950_000));
+            }
+        }
+    }
+}
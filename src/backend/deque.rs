@@ -0,0 +1,34 @@
+//! A small work-stealing deque.
+//! The owner pushes and pops at the back while thieves steal from the front,
+//! so the owner works depth first and thieves take the biggest tasks.
+//! Contention is low enough for a lock : only steals ever compete with the owner.
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+pub(crate) struct Deque<T> {
+    tasks: Mutex<VecDeque<T>>,
+}
+
+impl<T> Deque<T> {
+    pub(crate) fn new() -> Self {
+        Deque {
+            tasks: Mutex::new(VecDeque::new()),
+        }
+    }
+    /// Add a task (owner side).
+    pub(crate) fn push(&self, task: T) {
+        self.tasks.lock().expect("deque poisoned").push_back(task)
+    }
+    /// Take back the last pushed task (owner side).
+    pub(crate) fn pop(&self) -> Option<T> {
+        self.tasks.lock().expect("deque poisoned").pop_back()
+    }
+    /// Take the oldest task (thieves side).
+    pub(crate) fn steal(&self) -> Option<T> {
+        self.tasks.lock().expect("deque poisoned").pop_front()
+    }
+    /// Return whether there is nothing to take.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.tasks.lock().expect("deque poisoned").is_empty()
+    }
+}
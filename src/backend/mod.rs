@@ -0,0 +1,151 @@
+//! Where tasks run.
+//! Schedulers only go through the `Backend` trait to create parallelism.
+//! By default tasks run in rayon thread pools.
+//! With the `scoped-threads` feature they run instead on threads spawned with `std::thread::scope`
+//! for each outermost computation (no global pool is ever started).
+//! Tasks created through this module inherit the cancellation token of their creator
+//! (see `spawn_adaptive`).
+//! Their creator drops it while waiting for them since it might execute unrelated tasks meanwhile.
+use crate::cancellation::CurrentToken;
+#[cfg(feature = "scoped-threads")]
+mod deque;
+#[cfg(not(feature = "scoped-threads"))]
+mod pool;
+#[cfg(feature = "scoped-threads")]
+mod scoped;
+
+#[cfg(not(feature = "scoped-threads"))]
+pub(crate) type CurrentBackend = self::pool::RayonBackend;
+#[cfg(feature = "scoped-threads")]
+pub(crate) type CurrentBackend = self::scoped::ScopedBackend;
+
+/// Scope of the backend in use.
+pub(crate) type Scope<'scope> = <CurrentBackend as Backend>::Scope<'scope>;
+
+/// What a task knows about its execution.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FnContext {
+    migrated: bool,
+}
+
+impl FnContext {
+    pub(crate) fn new(migrated: bool) -> Self {
+        FnContext { migrated }
+    }
+    /// Return true if the task runs on another thread than the one which created it.
+    pub(crate) fn migrated(&self) -> bool {
+        self.migrated
+    }
+}
+
+/// Everything schedulers need for running tasks in parallel.
+pub(crate) trait Backend {
+    type Scope<'scope>: BackendScope<'scope>;
+    /// Execute both operations, potentially in parallel.
+    fn join_context<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+    where
+        A: FnOnce(FnContext) -> RA + Send,
+        B: FnOnce(FnContext) -> RB + Send,
+        RA: Send,
+        RB: Send;
+    /// Create a scope in which tasks can be spawned.
+    /// We only return once all of them completed.
+    fn scope<'scope, OP, R>(op: OP) -> R
+    where
+        OP: FnOnce(&Self::Scope<'scope>) -> R + Send + 'scope,
+        R: Send;
+    /// Run given operation in the background.
+    fn spawn<OP>(op: OP)
+    where
+        OP: FnOnce() + Send + 'static;
+    /// Number of threads tasks can run on.
+    fn current_num_threads() -> usize;
+    /// Run `op` on the current thread, computations it starts running on `threads` threads.
+    fn with_num_threads<OP, R>(threads: usize, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send;
+    /// Index of the current thread among them (None if outside).
+    /// Only tests need it.
+    #[cfg(test)]
+    fn current_thread_index() -> Option<usize>;
+}
+
+/// Scope in which tasks get spawned (see `Backend::scope`).
+pub(crate) trait BackendScope<'scope>: Sync {
+    /// Spawn given task, to be executed before the scope ends.
+    fn spawn<BODY>(&self, body: BODY)
+    where
+        BODY: FnOnce(&Self) + Send + 'scope;
+}
+
+pub(crate) fn join_context<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+where
+    A: FnOnce(FnContext) -> RA + Send,
+    B: FnOnce(FnContext) -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    let token = CurrentToken::get();
+    let _none = CurrentToken::none().enter();
+    CurrentBackend::join_context(
+        move |c| {
+            let _token = token.enter();
+            oper_a(c)
+        },
+        move |c| {
+            let _token = token.enter();
+            oper_b(c)
+        },
+    )
+}
+
+pub(crate) fn scope<'scope, OP, R>(op: OP) -> R
+where
+    OP: FnOnce(&Scope<'scope>) -> R + Send + 'scope,
+    R: Send,
+{
+    let token = CurrentToken::get();
+    let _none = CurrentToken::none().enter();
+    CurrentBackend::scope(move |s| {
+        let _token = token.enter();
+        op(s)
+    })
+}
+
+/// Spawn given task in given scope.
+pub(crate) fn spawn_in<'scope, BODY>(scope: &Scope<'scope>, body: BODY)
+where
+    BODY: FnOnce(&Scope<'scope>) + Send + 'scope,
+{
+    let token = CurrentToken::get();
+    BackendScope::spawn(scope, move |s| {
+        let _token = token.enter();
+        body(s)
+    })
+}
+
+pub(crate) fn spawn<OP>(op: OP)
+where
+    OP: FnOnce() + Send + 'static,
+{
+    CurrentBackend::spawn(op)
+}
+
+pub(crate) fn current_num_threads() -> usize {
+    CurrentBackend::current_num_threads()
+}
+
+pub(crate) fn with_num_threads<OP, R>(threads: usize, op: OP) -> R
+where
+    OP: FnOnce() -> R + Send,
+    R: Send,
+{
+    assert!(threads > 0, "we need at least one thread");
+    CurrentBackend::with_num_threads(threads, op)
+}
+
+#[cfg(test)]
+pub(crate) fn current_thread_index() -> Option<usize> {
+    CurrentBackend::current_thread_index()
+}
@@ -0,0 +1,60 @@
+//! Default backend : tasks run in the current rayon thread pool.
+use super::{Backend, BackendScope, FnContext};
+
+pub(crate) struct RayonBackend;
+
+impl Backend for RayonBackend {
+    type Scope<'scope> = rayon::Scope<'scope>;
+    fn join_context<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+    where
+        A: FnOnce(FnContext) -> RA + Send,
+        B: FnOnce(FnContext) -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        rayon::join_context(
+            |c| oper_a(FnContext::new(c.migrated())),
+            |c| oper_b(FnContext::new(c.migrated())),
+        )
+    }
+    fn scope<'scope, OP, R>(op: OP) -> R
+    where
+        OP: FnOnce(&Self::Scope<'scope>) -> R + Send + 'scope,
+        R: Send,
+    {
+        rayon::scope(op)
+    }
+    fn spawn<OP>(op: OP)
+    where
+        OP: FnOnce() + Send + 'static,
+    {
+        rayon_core::spawn(op)
+    }
+    fn current_num_threads() -> usize {
+        rayon_core::current_num_threads()
+    }
+    fn with_num_threads<OP, R>(threads: usize, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send,
+    {
+        rayon_core::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .expect("failed building pool")
+            .install(op)
+    }
+    #[cfg(test)]
+    fn current_thread_index() -> Option<usize> {
+        rayon_core::current_thread_index()
+    }
+}
+
+impl<'scope> BackendScope<'scope> for rayon::Scope<'scope> {
+    fn spawn<BODY>(&self, body: BODY)
+    where
+        BODY: FnOnce(&Self) + Send + 'scope,
+    {
+        rayon::Scope::spawn(self, body)
+    }
+}
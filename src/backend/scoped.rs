@@ -0,0 +1,433 @@
+//! Backend running tasks on threads spawned with `std::thread::scope`.
+//! The outermost computation spawns the workers and becomes worker 0 ; nested computations
+//! just run on the workers already there. Workers only live as long as the outermost computation.
+//! Each worker owns a deque of tasks. Idle workers steal from the others and workers waiting for
+//! a stolen task execute other tasks meanwhile.
+//! When there is nothing to execute they back off and finally sleep until some task gets pushed
+//! or completed.
+//! Workers of a computation started in a pool built from a `NumaLayout` take the NUMA nodes
+//! of its threads (without getting pinned).
+use super::deque::Deque;
+use super::{Backend, BackendScope, FnContext};
+use crate::numa::{current_layout, enter_node};
+use crossbeam::utils::Backoff;
+use std::any::Any;
+use std::cell::{Cell, UnsafeCell};
+use std::env;
+use std::marker::PhantomData;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// A type erased task, living on the stack of a join or on the heap.
+#[derive(Clone, Copy)]
+struct TaskRef {
+    pointer: *const (),
+    execute_fn: unsafe fn(*const (), bool),
+}
+
+// tasks are executed exactly once, before their creator gets rid of them
+unsafe impl Send for TaskRef {}
+
+impl TaskRef {
+    /// Run the task. `migrated` tells if we are not the thread which created it.
+    unsafe fn execute(self, migrated: bool) {
+        (self.execute_fn)(self.pointer, migrated)
+    }
+}
+
+/// Second operation of a join, waiting on the stack of the first one.
+struct StackTask<F, R> {
+    operation: UnsafeCell<Option<F>>,
+    result: UnsafeCell<Option<thread::Result<R>>>,
+    done: AtomicBool,
+}
+
+impl<F: FnOnce(bool) -> R, R> StackTask<F, R> {
+    fn new(operation: F) -> Self {
+        StackTask {
+            operation: UnsafeCell::new(Some(operation)),
+            result: UnsafeCell::new(None),
+            done: AtomicBool::new(false),
+        }
+    }
+    fn as_task_ref(&self) -> TaskRef {
+        TaskRef {
+            pointer: self as *const Self as *const (),
+            execute_fn: Self::execute,
+        }
+    }
+    unsafe fn execute(this: *const (), migrated: bool) {
+        let this = &*(this as *const Self);
+        let operation = (*this.operation.get()).take().expect("task executed twice");
+        *this.result.get() = Some(catch_unwind(AssertUnwindSafe(|| operation(migrated))));
+        // the creator might free us as soon as we are done
+        this.done.store(true, Ordering::Release);
+        wake_up_sleepers();
+    }
+    fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+    /// We took the task back before anyone stole it : just run it.
+    fn run_inline(self) -> R {
+        let operation = self.operation.into_inner().expect("task executed twice");
+        operation(false)
+    }
+    /// Get the result of a completed task, propagating its panic if any.
+    fn into_result(self) -> R {
+        match self.result.into_inner().expect("task not completed") {
+            Ok(result) => result,
+            Err(panic) => resume_unwind(panic),
+        }
+    }
+}
+
+/// Task spawned in a scope.
+struct HeapTask<BODY> {
+    body: BODY,
+}
+
+impl<BODY: FnOnce(bool)> HeapTask<BODY> {
+    fn into_task_ref(self: Box<Self>) -> TaskRef {
+        TaskRef {
+            pointer: Box::into_raw(self) as *const (),
+            execute_fn: Self::execute,
+        }
+    }
+    unsafe fn execute(this: *const (), migrated: bool) {
+        let this = Box::from_raw(this as *mut Self);
+        (this.body)(migrated)
+    }
+}
+
+/// Workers of an outermost computation.
+struct Registry {
+    deques: Vec<Deque<TaskRef>>,
+    terminated: AtomicBool,
+    sleep: Sleep,
+}
+
+/// Stop all workers when the outermost computation ends (even by a panic).
+struct Termination<'r>(&'r Registry);
+
+impl<'r> Drop for Termination<'r> {
+    fn drop(&mut self) {
+        self.0.terminated.store(true, Ordering::Release);
+        self.0.sleep.notify()
+    }
+}
+
+/// Where workers with nothing to execute sleep.
+struct Sleep {
+    sleepers: AtomicUsize,
+    lock: Mutex<()>,
+    condvar: Condvar,
+}
+
+impl Sleep {
+    fn new() -> Self {
+        Sleep {
+            sleepers: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            condvar: Condvar::new(),
+        }
+    }
+    /// Wake up all sleepers. Call it once a task got pushed or completed.
+    fn notify(&self) {
+        // pairs with the fence in `Worker::sleep` : either the sleeper sees our change
+        // or we see it registered.
+        fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::Relaxed) != 0 {
+            let _lock = self.lock.lock().expect("sleep poisoned");
+            self.condvar.notify_all()
+        }
+    }
+}
+
+/// Wake up sleepers of the current thread's registry (if any).
+fn wake_up_sleepers() {
+    if let Some(worker) = current_worker() {
+        worker.registry.sleep.notify()
+    }
+}
+
+struct Worker<'r> {
+    registry: &'r Registry,
+    index: usize,
+}
+
+thread_local!(static CURRENT_WORKER: Cell<*const ()> = const { Cell::new(ptr::null()) });
+
+/// Return the worker running on the current thread (if any).
+fn current_worker<'r>() -> Option<&'r Worker<'r>> {
+    let worker = CURRENT_WORKER.with(Cell::get);
+    // the pointer is only set while the worker runs on our stack (see `Worker::run`)
+    unsafe { (worker as *const Worker<'r>).as_ref() }
+}
+
+/// Restore the previous worker of the thread when dropped.
+struct CurrentWorkerGuard {
+    previous: *const (),
+}
+
+impl Drop for CurrentWorkerGuard {
+    fn drop(&mut self) {
+        CURRENT_WORKER.with(|w| w.set(self.previous))
+    }
+}
+
+impl<'r> Worker<'r> {
+    /// Run given operation as the current thread's worker.
+    fn run<R, OP: FnOnce(&Self) -> R>(&self, op: OP) -> R {
+        let me = self as *const Self as *const ();
+        let _guard = CurrentWorkerGuard {
+            previous: CURRENT_WORKER.with(|w| w.replace(me)),
+        };
+        op(self)
+    }
+    fn push(&self, task: TaskRef) {
+        self.registry.deques[self.index].push(task);
+        self.registry.sleep.notify()
+    }
+    /// Find a task : our last one or else the oldest one of another worker.
+    /// Also tell if it got stolen.
+    fn find_task(&self) -> Option<(TaskRef, bool)> {
+        let deques = &self.registry.deques;
+        deques[self.index]
+            .pop()
+            .map(|task| (task, false))
+            .or_else(|| {
+                (1..deques.len())
+                    .map(|offset| (self.index + offset) % deques.len())
+                    .find_map(|victim| deques[victim].steal())
+                    .map(|task| (task, true))
+            })
+    }
+    /// Execute available tasks until given condition holds.
+    fn wait_until<C: Fn() -> bool>(&self, condition: C) {
+        let backoff = Backoff::new();
+        while !condition() {
+            match self.find_task() {
+                Some((task, migrated)) => {
+                    unsafe { task.execute(migrated) };
+                    backoff.reset()
+                }
+                None => self.idle(&backoff, &condition),
+            }
+        }
+    }
+    /// We found nothing to execute : back off and finally sleep.
+    fn idle<C: Fn() -> bool>(&self, backoff: &Backoff, condition: C) {
+        if backoff.is_completed() {
+            self.sleep(condition);
+            backoff.reset()
+        } else {
+            backoff.snooze()
+        }
+    }
+    /// Sleep until some task gets pushed or completed, unless given condition holds.
+    /// We might wake up spuriously.
+    fn sleep<C: Fn() -> bool>(&self, condition: C) {
+        let sleep = &self.registry.sleep;
+        let lock = sleep.lock.lock().expect("sleep poisoned");
+        sleep.sleepers.fetch_add(1, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        // check again now that everyone can see us
+        let nothing_to_do = self.registry.deques.iter().all(Deque::is_empty);
+        if nothing_to_do && !condition() {
+            drop(sleep.condvar.wait(lock).expect("sleep poisoned"));
+        }
+        sleep.sleepers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+thread_local!(static NUM_THREADS: Cell<Option<usize>> = const { Cell::new(None) });
+
+/// Threads used by outermost computations.
+/// Inside `with_num_threads` we use the number it got.
+/// Called from a rayon pool (with `install`) we use as many threads as it has.
+/// Otherwise `RAYON_NUM_THREADS` is honored, like rayon does, and we default to all cores.
+fn default_num_threads() -> usize {
+    if let Some(threads) = NUM_THREADS.with(Cell::get) {
+        return threads;
+    }
+    if rayon_core::current_thread_index().is_some() {
+        return rayon_core::current_num_threads();
+    }
+    env::var("RAYON_NUM_THREADS")
+        .ok()
+        .and_then(|threads| threads.parse().ok())
+        .filter(|&threads| threads > 0)
+        .or_else(|| thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+}
+
+/// Restore the previous number of threads of `with_num_threads` when dropped.
+struct NumThreadsGuard(Option<usize>);
+
+impl Drop for NumThreadsGuard {
+    fn drop(&mut self) {
+        NUM_THREADS.with(|num_threads| num_threads.set(self.0))
+    }
+}
+
+/// Run given operation on a worker, spawning them if we are not on one already.
+fn in_worker<R, OP: FnOnce(&Worker) -> R>(op: OP) -> R {
+    if let Some(worker) = current_worker() {
+        return op(worker);
+    }
+    let registry = Registry {
+        deques: (0..default_num_threads()).map(|_| Deque::new()).collect(),
+        terminated: AtomicBool::new(false),
+        sleep: Sleep::new(),
+    };
+    let registry = &registry;
+    let layout = &current_layout();
+    thread::scope(|s| {
+        for index in 1..registry.deques.len() {
+            s.spawn(move || {
+                let _node = layout.as_ref().map(|layout| enter_node(layout, index));
+                Worker { registry, index }.run(|worker| {
+                    worker.wait_until(|| worker.registry.terminated.load(Ordering::Acquire))
+                })
+            });
+        }
+        let _termination = Termination(registry);
+        let _node = layout.as_ref().map(|layout| enter_node(layout, 0));
+        Worker { registry, index: 0 }.run(op)
+    })
+}
+
+pub(crate) struct ScopedBackend;
+
+impl Backend for ScopedBackend {
+    type Scope<'scope> = ThreadsScope<'scope>;
+    fn join_context<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+    where
+        A: FnOnce(FnContext) -> RA + Send,
+        B: FnOnce(FnContext) -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        in_worker(|worker| {
+            let task_b = StackTask::new(|migrated| oper_b(FnContext::new(migrated)));
+            let task_b_ref = task_b.as_task_ref();
+            worker.push(task_b_ref);
+            let result_a = catch_unwind(AssertUnwindSafe(|| oper_a(FnContext::new(false))));
+            // even if a panicked we wait for b since it borrows our stack
+            let backoff = Backoff::new();
+            let result_b = loop {
+                if task_b.is_done() {
+                    break task_b.into_result();
+                }
+                match worker.find_task() {
+                    Some((task, _)) if task.pointer == task_b_ref.pointer => {
+                        break task_b.run_inline()
+                    }
+                    Some((task, migrated)) => {
+                        unsafe { task.execute(migrated) };
+                        backoff.reset()
+                    }
+                    None => worker.idle(&backoff, || task_b.is_done()),
+                }
+            };
+            match result_a {
+                Ok(result_a) => (result_a, result_b),
+                Err(panic) => resume_unwind(panic),
+            }
+        })
+    }
+    fn scope<'scope, OP, R>(op: OP) -> R
+    where
+        OP: FnOnce(&Self::Scope<'scope>) -> R + Send + 'scope,
+        R: Send,
+    {
+        in_worker(|worker| {
+            let scope = ThreadsScope {
+                pending_tasks: AtomicUsize::new(0),
+                panic: Mutex::new(None),
+                marker: PhantomData,
+            };
+            let result = catch_unwind(AssertUnwindSafe(|| op(&scope)));
+            worker.wait_until(|| scope.pending_tasks.load(Ordering::Acquire) == 0);
+            if let Some(panic) = scope.panic.into_inner().expect("scope poisoned") {
+                resume_unwind(panic)
+            }
+            match result {
+                Ok(result) => result,
+                Err(panic) => resume_unwind(panic),
+            }
+        })
+    }
+    fn spawn<OP>(op: OP)
+    where
+        OP: FnOnce() + Send + 'static,
+    {
+        // the thread becomes worker 0 of the computations it starts
+        thread::spawn(op);
+    }
+    fn current_num_threads() -> usize {
+        current_worker().map_or_else(default_num_threads, |worker| worker.registry.deques.len())
+    }
+    fn with_num_threads<OP, R>(threads: usize, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send,
+    {
+        // we only start outermost computations with our own threads
+        if current_worker().is_some() {
+            return thread::scope(|s| {
+                s.spawn(|| Self::with_num_threads(threads, op))
+                    .join()
+                    .unwrap_or_else(|panic| resume_unwind(panic))
+            });
+        }
+        let previous = NUM_THREADS.with(|num_threads| num_threads.replace(Some(threads)));
+        let _restore = NumThreadsGuard(previous);
+        op()
+    }
+    #[cfg(test)]
+    fn current_thread_index() -> Option<usize> {
+        current_worker().map(|worker| worker.index)
+    }
+}
+
+/// Scope of the scoped threads backend.
+pub(crate) struct ThreadsScope<'scope> {
+    pending_tasks: AtomicUsize,
+    /// First panic of a spawned task, propagated when the scope ends.
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+    marker: PhantomData<&'scope mut &'scope ()>,
+}
+
+impl<'scope> ThreadsScope<'scope> {
+    fn execute<BODY: FnOnce(&Self)>(&self, body: BODY) {
+        if let Err(panic) = catch_unwind(AssertUnwindSafe(|| body(self))) {
+            self.panic
+                .lock()
+                .expect("scope poisoned")
+                .get_or_insert(panic);
+        }
+        // the scope might end as soon as we are done
+        self.pending_tasks.fetch_sub(1, Ordering::Release);
+        wake_up_sleepers();
+    }
+}
+
+impl<'scope> BackendScope<'scope> for ThreadsScope<'scope> {
+    fn spawn<BODY>(&self, body: BODY)
+    where
+        BODY: FnOnce(&Self) + Send + 'scope,
+    {
+        let worker = current_worker().expect("spawning outside of the scope's workers");
+        self.pending_tasks.fetch_add(1, Ordering::Relaxed);
+        // the scope waits for all its tasks so it outlives them
+        let scope = self as *const Self;
+        let task = Box::new(HeapTask {
+            body: move |_| unsafe { (*scope).execute(body) },
+        });
+        worker.push(task.into_task_ref())
+    }
+}
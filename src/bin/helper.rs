@@ -1,6 +1,7 @@
 #[cfg(feature = "logs")]
 extern crate rayon_logs as rayon;
 use rayon::ThreadPoolBuilder;
+use std::io;
 use std::iter::repeat;
 
 use rayon_adaptive::prelude::*;
@@ -28,19 +29,9 @@ fn main() {
     pool.install(|| {
         (0..10_000)
             .into_adapt_iter()
-            .map(|e| f(e))
+            .map(f)
             .by_blocks(repeat(5000))
-            .fold(Vec::new, |mut v, e| {
-                v.push(e);
-                v
-            })
-            .helping_for_each(
-                |e| println!("{}", e),
-                |v| {
-                    for e in v {
-                        println!("{}", e);
-                    }
-                },
-            )
+            .write_ordered(io::stdout(), |w, e| writeln!(w, "{}", e))
+            .expect("failed writing results")
     })
 }
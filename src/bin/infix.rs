@@ -1,26 +1,11 @@
 use rayon_adaptive::*;
-use time::precise_time_ns;
 #[cfg(feature = "logs")]
 extern crate rayon_logs as rayon;
-
+#[cfg(feature = "logs")]
 use rayon::ThreadPoolBuilder;
-use std::iter::repeat_with;
 const NUM_THREADS: usize = 2;
 const SIZE: u64 = 1_000_000;
 
-fn print_timing<U, I: Send + Sized, G: Fn() -> I, F: Fn(I) -> U>(label: &str, setup: G, f: F) {
-    let time: u64 = repeat_with(setup)
-        .take(100)
-        .map(|v| {
-            let start = precise_time_ns();
-            f(v);
-            let end = precise_time_ns();
-            end - start
-        })
-        .sum();
-    println!("{} : {}", label, time / 100);
-}
-
 fn main() {
     #[cfg(feature = "logs")]
     {
@@ -64,33 +49,18 @@ fn main() {
     }
     #[cfg(not(feature = "logs"))]
     {
-        ThreadPoolBuilder::new()
-            .num_threads(NUM_THREADS)
-            .build_global()
-            .expect("Pool creation failed");
-
+        // comparison checks all answers agree
         let random_expression = vec_gen(SIZE);
-        let answer = solver_seq(&random_expression);
-        let adapt_ans = solver_adaptive(&random_expression, Default::default());
-        let parsplit_ans = solver_par_split(&random_expression);
-        let parfold_ans = solver_par_fold(&random_expression);
-        let adaptfold_ans = solver_fully_adaptive(&random_expression);
-        assert_eq!(answer, adapt_ans);
-        assert_eq!(answer, parsplit_ans);
-        assert_eq!(answer, parfold_ans);
-        assert_eq!(answer, adaptfold_ans);
-        print_timing("sequential", || vec_gen(SIZE), |v| solver_seq(&v));
-        print_timing("par split", || vec_gen(SIZE), |v| solver_par_split(&v));
-        print_timing("par fold", || vec_gen(SIZE), |v| solver_par_fold(&v));
-        print_timing(
-            "adapt",
-            || vec_gen(SIZE),
-            |v| solver_adaptive(&v, Default::default()),
-        );
-        print_timing(
-            "fully_adapt",
-            || vec_gen(SIZE),
-            |v| solver_fully_adaptive(&v),
-        );
+        let setup = || random_expression.as_slice();
+        let results = Comparison::new()
+            .runs_number(100)
+            .threads(vec![NUM_THREADS])
+            .attach_sequential_with_setup("sequential", setup, solver_seq)
+            .attach_algorithm_with_setup("par split", setup, |v, _| solver_par_split(v))
+            .attach_algorithm_with_setup("par fold", setup, |v, _| solver_par_fold(v))
+            .attach_algorithm_with_setup("adapt", setup, solver_adaptive)
+            .attach_algorithm_with_setup("fully_adapt", setup, |v, _| solver_fully_adaptive(v))
+            .run();
+        println!("{}", results);
     }
 }
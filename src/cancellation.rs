@@ -0,0 +1,262 @@
+//! Cooperative cancellation of running computations.
+//! Once the token is cancelled, all inputs attached to it report a length of 0.
+//! Workers check it between blocks so they stop taking new work
+//! and the computation returns what was done so far.
+//! Computations started with `spawn_adaptive` have their own token, inherited by all their tasks.
+//! Once it is cancelled they unwind at their next block instead.
+//! Threads waiting for tasks might run unrelated ones meanwhile,
+//! so the token is only set while running tasks of its computation.
+use crate::prelude::*;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::panic::resume_unwind;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+thread_local!(static CURRENT_TOKEN: Cell<*const CancellationToken> = const { Cell::new(ptr::null()) });
+
+/// Shared flag for aborting computations from outside.
+///
+/// # Example
+///
+/// ```
+/// use rayon_adaptive::prelude::*;
+/// use rayon_adaptive::CancellationToken;
+/// let token = CancellationToken::new();
+/// let count = (0..1_000_000)
+///     .into_adapt_iter()
+///     .with_cancellation(&token)
+///     .fold(
+///         || 0,
+///         |c, e| {
+///             if e == 1_000 {
+///                 token.cancel()
+///             }
+///             c + 1
+///         },
+///     )
+///     .reduce(|c1, c2| c1 + c2);
+/// assert!(count < 1_000_000);
+/// assert!(token.result(count).is_err());
+/// ```
+#[derive(Default)]
+pub struct CancellationToken {
+    cancelled: AtomicBool,
+}
+
+/// Error returned when a computation got cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "computation cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken {
+            cancelled: AtomicBool::new(false),
+        }
+    }
+    /// Ask all computations attached to us to stop.
+    /// This can be called from any thread.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed)
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+    /// Return given (possibly partial) result if we were not cancelled.
+    pub fn result<R>(&self, result: R) -> Result<R, Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(result)
+        }
+    }
+    /// Attach all tasks run by the current thread to us, until the guard drops.
+    /// We must outlive all of them.
+    pub(crate) fn enter(&self) -> TokenGuard {
+        CurrentToken(self).enter()
+    }
+}
+
+/// Token of the spawned computation the current task belongs to (if any).
+#[derive(Clone, Copy)]
+pub(crate) struct CurrentToken(*const CancellationToken);
+
+// tokens outlive all tasks of their computation
+unsafe impl Send for CurrentToken {}
+
+impl CurrentToken {
+    pub(crate) fn get() -> Self {
+        CurrentToken(CURRENT_TOKEN.with(Cell::get))
+    }
+    /// No token : tasks never get cancelled.
+    pub(crate) fn none() -> Self {
+        CurrentToken(ptr::null())
+    }
+    /// Make it the token of the current thread until the guard drops.
+    /// Tasks call it with the token of the task which created them.
+    pub(crate) fn enter(self) -> TokenGuard {
+        TokenGuard {
+            previous: CURRENT_TOKEN.with(|t| t.replace(self.0)),
+        }
+    }
+    pub(crate) fn is_cancelled(self) -> bool {
+        unsafe { self.0.as_ref() }.is_some_and(CancellationToken::is_cancelled)
+    }
+}
+
+/// Restore the previous token of the thread when dropped.
+pub(crate) struct TokenGuard {
+    previous: *const CancellationToken,
+}
+
+impl Drop for TokenGuard {
+    fn drop(&mut self) {
+        CURRENT_TOKEN.with(|t| t.set(self.previous))
+    }
+}
+
+/// Stop the current task if its spawned computation got cancelled.
+/// We unwind with a `Cancelled` payload, caught by `spawn_adaptive`.
+pub(crate) fn check_cancellation() {
+    if CurrentToken::get().is_cancelled() {
+        resume_unwind(Box::new(Cancelled))
+    }
+}
+
+/// Input attached to a `CancellationToken`.
+/// Contrary to `AbortingDivisible` we cannot be collected by index
+/// since some parts might never get processed.
+pub struct Cancellable<'a, I> {
+    pub(crate) input: I,
+    pub(crate) token: &'a CancellationToken,
+}
+
+impl<'a, I: Divisible> Divisible for Cancellable<'a, I> {
+    type Power = I::Power;
+    fn base_length(&self) -> usize {
+        if self.token.is_cancelled() {
+            0
+        } else {
+            self.input.base_length()
+        }
+    }
+    fn divide(self) -> (Self, Self) {
+        let (left, right) = self.input.divide();
+        (
+            Cancellable {
+                input: left,
+                token: self.token,
+            },
+            Cancellable {
+                input: right,
+                token: self.token,
+            },
+        )
+    }
+}
+
+impl<'a, I: DivisibleIntoBlocks> DivisibleIntoBlocks for Cancellable<'a, I> {
+    fn divide_at(self, index: usize) -> (Self, Self) {
+        let (left, right) = self.input.divide_at(index);
+        (
+            Cancellable {
+                input: left,
+                token: self.token,
+            },
+            Cancellable {
+                input: right,
+                token: self.token,
+            },
+        )
+    }
+}
+
+impl<'a, I: IntoIterator> IntoIterator for Cancellable<'a, I> {
+    type IntoIter = I::IntoIter;
+    type Item = I::Item;
+    fn into_iter(self) -> Self::IntoIter {
+        self.input.into_iter()
+    }
+}
+
+impl<'a, I: AdaptiveIterator> AdaptiveIterator for Cancellable<'a, I> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Policy;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    #[test]
+    fn cancelled_inputs_look_empty() {
+        let token = CancellationToken::new();
+        let input = Cancellable {
+            input: 0..100,
+            token: &token,
+        };
+        let (left, right) = input.divide();
+        assert_eq!((left.base_length(), right.base_length()), (50, 50));
+        token.cancel();
+        assert_eq!((left.base_length(), right.base_length()), (0, 0));
+        assert_eq!(left.input, 0..50);
+    }
+
+    #[test]
+    fn results_are_errors_once_cancelled() {
+        let token = CancellationToken::new();
+        assert_eq!(token.result(3), Ok(3));
+        token.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(token.result(3), Err(Cancelled));
+    }
+
+    #[test]
+    fn cancelling_while_stealing() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .expect("pool creation failed");
+        for _ in 0..100 {
+            let token = CancellationToken::new();
+            let folded = AtomicUsize::new(0);
+            let count = pool.install(|| {
+                rayon::join(
+                    || {
+                        (0..1_000_000)
+                            .into_adapt_iter()
+                            .with_policy(Policy::Adaptive(1, 100))
+                            .with_cancellation(&token)
+                            .fold(
+                                || 0,
+                                |c, _| {
+                                    folded.fetch_add(1, Ordering::Relaxed);
+                                    c + 1
+                                },
+                            )
+                            .reduce(|c1, c2| c1 + c2)
+                    },
+                    || {
+                        // cancel from outside, while thieves come and go
+                        while folded.load(Ordering::Relaxed) < 1_000 {
+                            thread::yield_now()
+                        }
+                        token.cancel()
+                    },
+                )
+                .0
+            });
+            assert!(count < 1_000_000);
+            assert_eq!(token.result(count), Err(Cancelled));
+        }
+    }
+}
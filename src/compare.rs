@@ -0,0 +1,339 @@
+//! Compare algorithms over several scheduling policies and numbers of threads.
+//! Each algorithm is registered with a setup closure generating its input.
+//! Setups are not timed. All results must agree.
+use crate::backend::{current_num_threads, with_num_threads};
+use crate::Policy;
+use std::fmt;
+use std::io::{self, Write};
+use std::iter::once;
+use std::time::{Duration, Instant};
+
+/// Setup, run and time an algorithm with a given policy.
+type TimedRun<'a, R> = Box<dyn Fn(Policy) -> (R, Duration) + Sync + 'a>;
+
+struct Algorithm<'a, R> {
+    name: String,
+    run: TimedRun<'a, R>,
+}
+
+/// Algorithms to compare, together with policies and threads numbers to compare them on.
+///
+/// # Example
+///
+/// ```
+/// use rayon_adaptive::prelude::*;
+/// use rayon_adaptive::{Comparison, Policy};
+/// let results = Comparison::new()
+///     .runs_number(3)
+///     .threads(vec![1, 2])
+///     .policies(vec![Policy::Join(1_000), Policy::Adaptive(1_000, 10_000)])
+///     .attach_sequential_with_setup("sequential", || (0..100_000).collect(), |v: Vec<u64>| {
+///         v.iter().sum::<u64>()
+///     })
+///     .attach_algorithm_with_setup("adaptive sum", || (0..100_000).collect(), |v: Vec<u64>, policy| {
+///         v.into_adapt_iter()
+///             .with_policy(policy)
+///             .fold(|| 0, |s, e| s + e)
+///             .reduce(|a, b| a + b)
+///     })
+///     .run();
+/// assert_eq!(results.measures().len(), 5);
+/// println!("{}", results);
+/// ```
+pub struct Comparison<'a, R> {
+    sequential: Option<Algorithm<'a, R>>,
+    algorithms: Vec<Algorithm<'a, R>>,
+    policies: Vec<Policy>,
+    threads: Vec<usize>,
+    runs_number: usize,
+}
+
+impl<'a, R: PartialEq + Send> Default for Comparison<'a, R> {
+    fn default() -> Self {
+        Comparison::new()
+    }
+}
+
+impl<'a, R: PartialEq + Send> Comparison<'a, R> {
+    /// Create a new comparison with default policy, running on 1, 2, 4, ... threads
+    /// up to the current number of threads.
+    pub fn new() -> Self {
+        let max_threads = current_num_threads();
+        Comparison {
+            sequential: None,
+            algorithms: Vec::new(),
+            policies: vec![Policy::DefaultPolicy],
+            threads: (0..)
+                .map(|power| 1 << power)
+                .take_while(|&threads| threads < max_threads)
+                .chain(once(max_threads))
+                .collect(),
+            runs_number: 10,
+        }
+    }
+    /// Set the number of runs for each measure (we keep the median).
+    pub fn runs_number(mut self, runs_number: usize) -> Self {
+        assert!(runs_number > 0, "we need at least one run");
+        self.runs_number = runs_number;
+        self
+    }
+    /// Set all policies to compare.
+    pub fn policies(mut self, policies: Vec<Policy>) -> Self {
+        self.policies = policies;
+        self
+    }
+    /// Set all numbers of threads to compare.
+    pub fn threads(mut self, threads: Vec<usize>) -> Self {
+        self.threads = threads;
+        self
+    }
+    /// Set the sequential algorithm all speedups are computed against.
+    /// It runs on one thread.
+    pub fn attach_sequential_with_setup<I, G, F>(
+        mut self,
+        name: &str,
+        setup: G,
+        algorithm: F,
+    ) -> Self
+    where
+        G: Fn() -> I + Sync + 'a,
+        F: Fn(I) -> R + Sync + 'a,
+    {
+        self.sequential = Some(Algorithm {
+            name: name.to_owned(),
+            run: timed(setup, move |input, _| algorithm(input)),
+        });
+        self
+    }
+    /// Add an algorithm running with the policy it is given.
+    pub fn attach_algorithm_with_setup<I, G, F>(
+        mut self,
+        name: &str,
+        setup: G,
+        algorithm: F,
+    ) -> Self
+    where
+        G: Fn() -> I + Sync + 'a,
+        F: Fn(I, Policy) -> R + Sync + 'a,
+    {
+        self.algorithms.push(Algorithm {
+            name: name.to_owned(),
+            run: timed(setup, algorithm),
+        });
+        self
+    }
+    /// Run everything, checking all results agree.
+    /// This panics if one algorithm disagrees with the first one we ran.
+    pub fn run(&self) -> ComparisonResults {
+        let mut reference = None;
+        let mut measures = Vec::new();
+        let baseline = self.sequential.as_ref().map(|sequential| {
+            let median = self.measure(sequential, Policy::Sequential, 1, &mut reference);
+            measures.push(Measure {
+                algorithm: sequential.name.clone(),
+                policy: None,
+                threads: 1,
+                median,
+                speedup: Some(1.0),
+            });
+            median
+        });
+        for algorithm in &self.algorithms {
+            for &policy in &self.policies {
+                for &threads in &self.threads {
+                    let median = self.measure(algorithm, policy, threads, &mut reference);
+                    measures.push(Measure {
+                        algorithm: algorithm.name.clone(),
+                        policy: Some(policy),
+                        threads,
+                        median,
+                        speedup: baseline.map(|baseline| seconds(baseline) / seconds(median)),
+                    })
+                }
+            }
+        }
+        ComparisonResults { measures }
+    }
+    /// Return median time of all runs of given algorithm.
+    fn measure(
+        &self,
+        algorithm: &Algorithm<'a, R>,
+        policy: Policy,
+        threads: usize,
+        reference: &mut Option<(R, String)>,
+    ) -> Duration {
+        // results are kept until all runs are done so that checking them is not timed
+        let runs: Vec<(R, Duration)> = with_num_threads(threads, || {
+            (0..self.runs_number)
+                .map(|_| (algorithm.run)(policy))
+                .collect()
+        });
+        let mut times = Vec::with_capacity(runs.len());
+        for (result, time) in runs {
+            match reference {
+                Some((expected, reference_name)) => assert!(
+                    result == *expected,
+                    "{} ({} on {} threads) disagrees with {}",
+                    algorithm.name,
+                    policy,
+                    threads,
+                    reference_name
+                ),
+                None => *reference = Some((result, algorithm.name.clone())),
+            }
+            times.push(time);
+        }
+        times.sort();
+        times[times.len() / 2]
+    }
+}
+
+fn timed<'a, I, R, G, F>(setup: G, algorithm: F) -> TimedRun<'a, R>
+where
+    G: Fn() -> I + Sync + 'a,
+    F: Fn(I, Policy) -> R + Sync + 'a,
+{
+    Box::new(move |policy| {
+        let input = setup();
+        let start = Instant::now();
+        let result = algorithm(input, policy);
+        (result, start.elapsed())
+    })
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+/// Median time of an algorithm for a given policy and number of threads.
+#[derive(Debug, Clone)]
+pub struct Measure {
+    pub algorithm: String,
+    /// None for the sequential algorithm.
+    pub policy: Option<Policy>,
+    pub threads: usize,
+    pub median: Duration,
+    /// None if no sequential algorithm was attached.
+    pub speedup: Option<f64>,
+}
+
+impl Measure {
+    fn fields(&self) -> [String; 5] {
+        [
+            self.algorithm.clone(),
+            self.policy
+                .map(|policy| policy.to_string())
+                .unwrap_or_else(|| "-".to_owned()),
+            self.threads.to_string(),
+            format!("{:.3}", seconds(self.median) * 1e3),
+            self.speedup
+                .map(|speedup| format!("{:.2}", speedup))
+                .unwrap_or_else(|| "-".to_owned()),
+        ]
+    }
+}
+
+const HEADERS: [&str; 5] = ["algorithm", "policy", "threads", "median (ms)", "speedup"];
+
+/// All measures of a comparison.
+/// They display as a markdown table.
+pub struct ComparisonResults {
+    measures: Vec<Measure>,
+}
+
+impl ComparisonResults {
+    pub fn measures(&self) -> &[Measure] {
+        &self.measures
+    }
+    /// Write all measures as csv.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADERS.join(","))?;
+        for measure in &self.measures {
+            let fields: Vec<String> = measure
+                .fields()
+                .iter()
+                .map(|field| format!("\"{}\"", field.replace('"', "\"\"")))
+                .collect();
+            writeln!(writer, "{}", fields.join(","))?;
+        }
+        writer.flush()
+    }
+}
+
+impl fmt::Display for ComparisonResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "| {} |", HEADERS.join(" | "))?;
+        writeln!(f, "|{}", "---|".repeat(HEADERS.len()))?;
+        for measure in &self.measures {
+            writeln!(f, "| {} |", measure.fields().join(" | "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Comparison;
+    use crate::backend::current_num_threads;
+    use crate::prelude::*;
+    use crate::Policy;
+    use std::sync::Mutex;
+
+    #[test]
+    fn algorithms_run_on_requested_threads() {
+        let seen_threads = Mutex::new(Vec::new());
+        let results = Comparison::new()
+            .runs_number(2)
+            .threads(vec![1, 3])
+            .policies(vec![Policy::Join(100), Policy::Adaptive(100, 1_000)])
+            .attach_sequential_with_setup(
+                "sequential",
+                || (0..10_000).collect(),
+                |v: Vec<u64>| v.iter().sum::<u64>(),
+            )
+            .attach_algorithm_with_setup(
+                "adaptive",
+                || (0..10_000).collect(),
+                |v: Vec<u64>, policy| {
+                    v.into_adapt_iter()
+                        .with_policy(policy)
+                        .fold(
+                            || 0,
+                            |s, e| {
+                                if *e == 0 {
+                                    seen_threads.lock().unwrap().push(current_num_threads());
+                                }
+                                s + e
+                            },
+                        )
+                        .reduce(|a, b| a + b)
+                },
+            )
+            .run();
+        // one sequential measure then one per policy and number of threads
+        let measures = results.measures();
+        assert_eq!(measures.len(), 5);
+        assert!(measures[0].policy.is_none());
+        assert_eq!(measures[0].speedup, Some(1.0));
+        assert!(measures[1..]
+            .iter()
+            .all(|measure| measure.speedup.is_some()));
+        let threads: Vec<usize> = measures.iter().map(|measure| measure.threads).collect();
+        assert_eq!(threads, vec![1, 1, 3, 1, 3]);
+        assert_eq!(*seen_threads.lock().unwrap(), vec![1, 1, 3, 3, 1, 1, 3, 3]);
+        let mut csv = Vec::new();
+        results.write_csv(&mut csv).expect("failed writing csv");
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 6);
+    }
+
+    #[test]
+    #[should_panic(expected = "disagrees with")]
+    fn disagreeing_algorithms_panic() {
+        Comparison::new()
+            .runs_number(1)
+            .threads(vec![2])
+            .attach_sequential_with_setup("right", || (), |_| 1)
+            .attach_algorithm_with_setup("wrong", || (), |_, _| 2)
+            .run();
+    }
+}
@@ -0,0 +1,220 @@
+//! Time bounded computations.
+//! Once the deadline is passed, inputs report a length of 0 so workers stop taking new blocks.
+//! Each input remembers its position in the initial input (in `base_length` units) and
+//! records what it still had to do when dropped after being stopped.
+//! This way we can tell which parts got processed.
+use crate::prelude::*;
+use std::cmp::max;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Result of a computation which might have been stopped by a deadline.
+#[derive(Debug)]
+pub struct Partial<T> {
+    /// What we computed on processed parts.
+    pub value: T,
+    /// Parts of the input (in `base_length` units) taken into account in `value`.
+    pub processed_ranges: Vec<Range<usize>>,
+}
+
+pub(crate) struct DeadlineTracker {
+    deadline: Instant,
+    expired: AtomicBool,
+    initial_length: usize,
+    skipped: Mutex<Vec<Range<usize>>>,
+}
+
+impl DeadlineTracker {
+    fn expired(&self) -> bool {
+        if self.expired.load(Ordering::Relaxed) {
+            true
+        } else if Instant::now() >= self.deadline {
+            self.expired.store(true, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+    /// Wrap given value with all ranges which were not skipped.
+    pub(crate) fn partial<T>(&self, value: T) -> Partial<T> {
+        let mut skipped = self
+            .skipped
+            .lock()
+            .expect("deadline tracker poisoned")
+            .clone();
+        skipped.sort_by_key(|r| r.start);
+        let mut processed_ranges = Vec::new();
+        let mut position = 0;
+        for range in skipped {
+            if range.start > position {
+                processed_ranges.push(position..range.start);
+            }
+            position = max(position, range.end);
+        }
+        if position < self.initial_length {
+            processed_ranges.push(position..self.initial_length);
+        }
+        Partial {
+            value,
+            processed_ranges,
+        }
+    }
+}
+
+/// Input attached to a deadline.
+/// Like `Cancellable` we cannot be collected by index.
+pub struct Timed<I> {
+    // only taken out when consuming ourselves
+    input: Option<I>,
+    offset: usize,
+    // what we still had to do when the deadline stopped us (0 if not stopped)
+    stopped_length: AtomicUsize,
+    pub(crate) tracker: Arc<DeadlineTracker>,
+}
+
+impl<I: Divisible> Timed<I> {
+    pub(crate) fn new(input: I, deadline: Instant) -> Self {
+        let initial_length = input.base_length();
+        Timed::attach(
+            input,
+            0,
+            Arc::new(DeadlineTracker {
+                deadline,
+                expired: AtomicBool::new(false),
+                initial_length,
+                skipped: Mutex::new(Vec::new()),
+            }),
+        )
+    }
+}
+
+impl<I> Timed<I> {
+    fn attach(input: I, offset: usize, tracker: Arc<DeadlineTracker>) -> Self {
+        Timed {
+            input: Some(input),
+            offset,
+            stopped_length: AtomicUsize::new(0),
+            tracker,
+        }
+    }
+    fn input(&self) -> &I {
+        self.input.as_ref().expect("timed input already consumed")
+    }
+    /// Take our content. Whoever gets it will process it, so nothing is skipped.
+    fn consume(mut self) -> (I, usize, Arc<DeadlineTracker>) {
+        let input = self.input.take().expect("timed input already consumed");
+        (input, self.offset, self.tracker.clone())
+    }
+}
+
+impl<I: Divisible> Timed<I> {
+    /// Rebuild both parts after a division.
+    /// The right part is anchored at our end since some inputs process data when dividing.
+    /// Parts of a stopped input are stopped too.
+    fn split<D: FnOnce(I) -> (I, I)>(mut self, division: D) -> (Self, Self) {
+        let stopped = *self.stopped_length.get_mut() != 0;
+        let (input, offset, tracker) = self.consume();
+        let length = input.base_length();
+        let (left, right) = division(input);
+        let right_offset = offset + length - right.base_length();
+        let (mut left, mut right) = (
+            Timed::attach(left, offset, tracker.clone()),
+            Timed::attach(right, right_offset, tracker),
+        );
+        if stopped {
+            left.stop();
+            right.stop();
+        }
+        (left, right)
+    }
+    fn stop(&mut self) {
+        let length = self.input().base_length();
+        *self.stopped_length.get_mut() = length;
+    }
+}
+
+/// Inputs reporting they were stopped by the deadline never get processed.
+/// We record what they skipped once, when they disappear.
+impl<I> Drop for Timed<I> {
+    fn drop(&mut self) {
+        let stopped_length = *self.stopped_length.get_mut();
+        if self.input.is_some() && stopped_length != 0 {
+            self.tracker
+                .skipped
+                .lock()
+                .expect("deadline tracker poisoned")
+                .push(self.offset..(self.offset + stopped_length));
+        }
+    }
+}
+
+impl<I: Divisible> Divisible for Timed<I> {
+    type Power = I::Power;
+    fn base_length(&self) -> usize {
+        let length = self.input().base_length();
+        if length != 0 && self.tracker.expired() {
+            self.stopped_length.store(length, Ordering::Relaxed);
+            0
+        } else {
+            length
+        }
+    }
+    fn divide(self) -> (Self, Self) {
+        self.split(Divisible::divide)
+    }
+}
+
+impl<I: DivisibleIntoBlocks> DivisibleIntoBlocks for Timed<I> {
+    fn divide_at(self, index: usize) -> (Self, Self) {
+        self.split(|input| input.divide_at(index))
+    }
+}
+
+impl<I: IntoIterator> IntoIterator for Timed<I> {
+    type IntoIter = I::IntoIter;
+    type Item = I::Item;
+    fn into_iter(self) -> Self::IntoIter {
+        self.consume().0.into_iter()
+    }
+}
+
+impl<I: AdaptiveIterator> AdaptiveIterator for Timed<I> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::Policy;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn deadlines_expiring_mid_run() {
+        for policy in &[
+            Policy::DefaultPolicy,
+            Policy::Join(100),
+            Policy::Adaptive(10, 100),
+        ] {
+            let partial = (0..100_000)
+                .into_adapt_iter()
+                .with_policy(*policy)
+                .with_deadline(Instant::now() + Duration::from_millis(50))
+                .fold(Vec::new, |mut v, e| {
+                    thread::sleep(Duration::from_micros(10));
+                    v.push(e);
+                    v
+                })
+                .reduce_until_deadline(|mut v1, v2| {
+                    v1.extend(v2);
+                    v1
+                });
+            let mut processed = partial.value;
+            processed.sort();
+            let covered: Vec<usize> = partial.processed_ranges.into_iter().flatten().collect();
+            assert!(!covered.is_empty());
+            assert!(covered.len() < 100_000);
+            assert_eq!(processed, covered);
+        }
+    }
+}
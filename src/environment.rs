@@ -0,0 +1,75 @@
+//! Overriding default policies from the environment, without recompiling.
+//! `RAYON_ADAPTIVE_POLICY` holds comma separated policies.
+//! A policy alone replaces the default policy everywhere.
+//! A `name=policy` entry only replaces it for call sites named with `.named("name")`.
+//! For example: `RAYON_ADAPTIVE_POLICY="join:1000,sort=adaptive:100:10000"`.
+//! Invalid entries are silently ignored : their call sites keep their own policies.
+use crate::Policy;
+use std::collections::HashMap;
+use std::env;
+use std::sync::OnceLock;
+
+const POLICY_VARIABLE: &str = "RAYON_ADAPTIVE_POLICY";
+
+#[derive(Default)]
+struct EnvironmentPolicies {
+    global: Option<Policy>,
+    named: HashMap<String, Policy>,
+}
+
+/// Parsed content of the variable, read on first use.
+static POLICIES: OnceLock<EnvironmentPolicies> = OnceLock::new();
+
+fn read_environment() -> EnvironmentPolicies {
+    env::var(POLICY_VARIABLE)
+        .map(|content| parse_policies(&content))
+        .unwrap_or_default()
+}
+
+fn parse_policies(content: &str) -> EnvironmentPolicies {
+    let mut policies = EnvironmentPolicies::default();
+    for entry in content.split(',').filter(|entry| !entry.trim().is_empty()) {
+        let mut fields = entry.splitn(2, '=');
+        match (fields.next(), fields.next()) {
+            (Some(name), Some(policy)) => {
+                if let Ok(policy) = policy.parse() {
+                    policies.named.insert(name.trim().to_owned(), policy);
+                }
+            }
+            _ => {
+                if let Ok(policy) = entry.parse() {
+                    policies.global = Some(policy)
+                }
+            }
+        }
+    }
+    policies
+}
+
+/// Return the policy replacing the default one for given call site (if any).
+pub(crate) fn environment_policy(name: Option<&str>) -> Option<Policy> {
+    let policies = POLICIES.get_or_init(read_environment);
+    match name {
+        Some(name) => policies.named.get(name).copied(),
+        None => policies.global,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_policies;
+
+    #[test]
+    fn invalid_entries_are_ignored() {
+        let policies = parse_policies("join:x, sort=adaptive:100, =, adaptive:10, tuned:");
+        assert!(policies.global.is_none());
+        assert!(policies.named.is_empty());
+        let policies = parse_policies("bad,join:1000,sort=adaptive:100:10000,merge=seq:1,");
+        assert_eq!(
+            policies.global.map(|policy| policy.to_string()),
+            Some("join:1000".to_owned())
+        );
+        assert_eq!(policies.named.len(), 1);
+        assert_eq!(policies.named["sort"].to_string(), "adaptive:100:10000");
+    }
+}
@@ -1,4 +1,6 @@
 //! map each folder's output to something else.
+#[cfg(any(feature = "stats", feature = "trace", test))]
+use crate::folders::SchedulingEvent;
 use crate::Folder;
 use std::marker::PhantomData;
 
@@ -32,4 +34,11 @@ where
     fn to_output(&self, io: Self::IntermediateOutput, i: Self::Input) -> Self::Output {
         (self.map_op)(self.inner_folder.to_output(io, i))
     }
+    fn processed(&self, size: usize) {
+        self.inner_folder.processed(size)
+    }
+    #[cfg(any(feature = "stats", feature = "trace", test))]
+    fn record(&self, event: SchedulingEvent) {
+        self.inner_folder.record(event)
+    }
 }
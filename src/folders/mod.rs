@@ -1,13 +1,55 @@
 //! Folder trait and all its implementations.
 use crate::Divisible;
 use std::marker::PhantomData;
+use std::time::Duration;
 mod map;
 pub use self::map::Map;
+mod pipe;
+pub(crate) use self::pipe::Pipe;
+mod progress;
+pub use self::progress::Progress;
 pub(crate) mod cutting_fold;
 pub(crate) mod fold;
 pub(crate) mod iterator_fold;
 pub(crate) mod work_fold;
 
+/// What happened while scheduling (see `Folder::record`).
+/// Only available with the `stats` or `trace` features, for collecting statistics or traces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(any(feature = "stats", feature = "trace")), allow(dead_code))]
+pub enum SchedulingEvent {
+    /// Input got divided in two.
+    Split,
+    /// A task got executed by another thread than the one which created it.
+    Steal,
+    /// An idle thread asked a working one for some of its input.
+    StealRequest,
+    /// A working thread gave away `given` elements of its input to an idle one
+    /// and kept `kept` of them (`remote` if the thief comes from another NUMA node).
+    StealAnswer {
+        given: usize,
+        kept: usize,
+        remote: bool,
+    },
+    /// The master of a helping fold folded a block of given size in given time
+    /// (only recorded with the `trace` feature, `processed` is always called).
+    MasterFold(usize, Duration),
+    /// Two outputs got reduced (or a helper's output got retrieved).
+    Reduction,
+    /// We stayed blocked, waiting for another thread.
+    Wait(Duration),
+}
+
+/// Tell given folder what happened while scheduling.
+/// Without the `stats` or `trace` features folders never hear about it.
+#[inline]
+pub(crate) fn record<F: Folder>(folder: &F, event: SchedulingEvent) {
+    #[cfg(any(feature = "stats", feature = "trace", test))]
+    folder.record(event);
+    #[cfg(not(any(feature = "stats", feature = "trace", test)))]
+    let _ = (folder, event);
+}
+
 // The *Folder* trait enables us to abstract other adaptive operations.
 // it takes a *Divisible* input, recursively cuts into smaller inputs,
 // fold producing intermediate outputs, maps these to final outputs.
@@ -25,6 +67,13 @@ pub trait Folder: Sync + Sized {
         limit: usize,
     ) -> (Self::IntermediateOutput, Self::Input);
     fn to_output(&self, io: Self::IntermediateOutput, i: Self::Input) -> Self::Output;
+    /// Called when a block of given size got folded.
+    /// Helping schedulers call it for blocks folded by the master, outside of us.
+    fn processed(&self, _size: usize) {}
+    /// Called by schedulers on each scheduling event
+    /// (only with the `stats` or `trace` features).
+    #[cfg(any(feature = "stats", feature = "trace", test))]
+    fn record(&self, _event: SchedulingEvent) {}
     fn map<O: Send, M: Fn(Self::Output) -> O + Sync>(self, map_op: M) -> Map<Self, O, M> {
         Map {
             inner_folder: self,
@@ -0,0 +1,77 @@
+//! fuse a folder with a consumer of its outputs.
+#[cfg(any(feature = "stats", feature = "trace", test))]
+use crate::folders::SchedulingEvent;
+use crate::{DivisibleIntoBlocks, Folder};
+
+/// Each block gets folded on its own and its output goes straight into the second stage,
+/// on the same thread. Nothing is left to reduce.
+#[must_use = "folders are lazy and do nothing unless consumed"]
+pub struct Pipe<'a, F, STAGE2> {
+    pub(crate) inner_folder: &'a F,
+    pub(crate) stage2: &'a STAGE2,
+}
+
+impl<'a, F, STAGE2> Folder for Pipe<'a, F, STAGE2>
+where
+    F: Folder,
+    F::Input: DivisibleIntoBlocks,
+    STAGE2: Fn(F::Output) + Sync,
+{
+    type Input = F::Input;
+    type IntermediateOutput = ();
+    type Output = ();
+    fn identity(&self) -> Self::IntermediateOutput {}
+    fn fold(
+        &self,
+        _io: Self::IntermediateOutput,
+        i: Self::Input,
+        limit: usize,
+    ) -> (Self::IntermediateOutput, Self::Input) {
+        let (block, remaining) = i.divide_at(limit);
+        let (io, block) = self
+            .inner_folder
+            .fold(self.inner_folder.identity(), block, limit);
+        (self.stage2)(self.inner_folder.to_output(io, block));
+        ((), remaining)
+    }
+    fn to_output(&self, _io: Self::IntermediateOutput, _i: Self::Input) -> Self::Output {}
+    // blocks folded by helping masters also go through `fold` so the inner folder
+    // already saw them : we do not forward `processed`.
+    #[cfg(any(feature = "stats", feature = "trace", test))]
+    fn record(&self, event: SchedulingEvent) {
+        self.inner_folder.record(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::Policy;
+    use std::iter::repeat;
+
+    #[test]
+    fn pipe_feeds_every_block_once() {
+        let expected: Vec<usize> = (0..100_000).collect();
+        for policy in &[
+            Policy::DefaultPolicy,
+            Policy::Sequential,
+            Policy::Join(100),
+            Policy::Adaptive(10, 100),
+            Policy::Rayon,
+        ] {
+            let blocks = std::sync::Mutex::new(Vec::new());
+            (0..100_000)
+                .into_adapt_iter()
+                .with_policy(*policy)
+                .by_blocks(repeat(30_000))
+                .fold(Vec::new, |mut v, e| {
+                    v.push(e);
+                    v
+                })
+                .pipe(|block| blocks.lock().unwrap().push(block));
+            let mut blocks = blocks.into_inner().unwrap();
+            blocks.sort_by_key(|block| block.first().cloned());
+            assert_eq!(blocks.concat(), expected);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! report how much of the input got folded so far.
+#[cfg(any(feature = "stats", feature = "trace", test))]
+use crate::folders::SchedulingEvent;
+use crate::{Divisible, Folder};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// We do not call the user's callback more often than that (except for the last block,
+/// which gets reported exactly once).
+const REPORTING_PERIOD: Duration = Duration::from_millis(100);
+
+#[must_use = "folders are lazy and do nothing unless consumed"]
+pub struct Progress<F: Folder, P: Fn(usize, usize) + Sync> {
+    pub(crate) inner_folder: F,
+    pub(crate) callback: P,
+    pub(crate) total: usize,
+    pub(crate) done: AtomicUsize,
+    pub(crate) last_report: Mutex<Instant>,
+    pub(crate) reported_end: AtomicBool,
+}
+
+impl<F: Folder, P: Fn(usize, usize) + Sync> Progress<F, P> {
+    pub(crate) fn new(inner_folder: F, callback: P, total: usize) -> Self {
+        Progress {
+            inner_folder,
+            callback,
+            total,
+            done: AtomicUsize::new(0),
+            last_report: Mutex::new(Instant::now()),
+            reported_end: AtomicBool::new(false),
+        }
+    }
+    /// Count given number of processed elements and maybe report.
+    fn advance(&self, size: usize) {
+        let done = self.done.fetch_add(size, Ordering::Relaxed) + size;
+        let finished = done >= self.total;
+        // only one thread reports at a time, others just go on working
+        let last_report = if finished {
+            self.last_report.lock().ok()
+        } else {
+            self.last_report.try_lock().ok()
+        };
+        if let Some(mut last_report) = last_report {
+            // nothing comes after the final report
+            if self.reported_end.load(Ordering::Relaxed) {
+                return;
+            }
+            let now = Instant::now();
+            if finished || now.duration_since(*last_report) >= REPORTING_PERIOD {
+                *last_report = now;
+                self.reported_end.store(finished, Ordering::Relaxed);
+                (self.callback)(done, self.total)
+            }
+        }
+    }
+}
+
+impl<F, P> Folder for Progress<F, P>
+where
+    F: Folder,
+    P: Fn(usize, usize) + Sync,
+{
+    type Input = F::Input;
+    type IntermediateOutput = F::IntermediateOutput;
+    type Output = F::Output;
+    fn identity(&self) -> Self::IntermediateOutput {
+        self.inner_folder.identity()
+    }
+    fn fold(
+        &self,
+        io: Self::IntermediateOutput,
+        i: Self::Input,
+        limit: usize,
+    ) -> (Self::IntermediateOutput, Self::Input) {
+        let initial_length = i.base_length();
+        let (io, remaining) = self.inner_folder.fold(io, i, limit);
+        self.advance(initial_length.saturating_sub(remaining.base_length()));
+        (io, remaining)
+    }
+    fn to_output(&self, io: Self::IntermediateOutput, i: Self::Input) -> Self::Output {
+        self.inner_folder.to_output(io, i)
+    }
+    fn processed(&self, size: usize) {
+        self.inner_folder.processed(size);
+        self.advance(size)
+    }
+    #[cfg(any(feature = "stats", feature = "trace", test))]
+    fn record(&self, event: SchedulingEvent) {
+        self.inner_folder.record(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Progress, REPORTING_PERIOD};
+    use crate::folders::fold::Fold;
+    use crate::prelude::*;
+    use crate::{Folder, Policy};
+    use std::marker::PhantomData;
+    use std::ops::Range;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+
+    #[test]
+    fn reports_are_rate_limited() {
+        let reports = Mutex::new(Vec::new());
+        let folder = Fold {
+            identity_op: || (),
+            fold_op: |_, r: Range<usize>, limit: usize| ((), r.divide_at(limit).1),
+            phantom: PhantomData,
+        };
+        let progress = Progress::new(folder, |done, _| reports.lock().unwrap().push(done), 100);
+        for _ in 0..10 {
+            progress.processed(5);
+        }
+        assert!(reports.lock().unwrap().is_empty());
+        thread::sleep(REPORTING_PERIOD);
+        let ((), remaining) = progress.fold((), 50..100, 5);
+        assert_eq!(remaining, 55..100);
+        progress.processed(5);
+        assert_eq!(*reports.lock().unwrap(), vec![55]);
+        progress.processed(40);
+        progress.processed(0);
+        thread::sleep(REPORTING_PERIOD);
+        progress.processed(0);
+        assert_eq!(*reports.lock().unwrap(), vec![55, 100]);
+    }
+
+    #[test]
+    fn completion_gets_reported_once() {
+        for policy in &[
+            Policy::DefaultPolicy,
+            Policy::Join(10),
+            Policy::Adaptive(10, 100),
+        ] {
+            let last_reports = AtomicUsize::new(0);
+            let sum = (0..100_000)
+                .into_adapt_iter()
+                .with_policy(*policy)
+                .fold(|| 0, |s, e| s + e)
+                .with_progress(|done, total| {
+                    assert!(done <= total);
+                    if done == total {
+                        last_reports.fetch_add(1, Ordering::SeqCst);
+                    }
+                })
+                .reduce(|s1, s2| s1 + s2);
+            assert_eq!(sum, 4_999_950_000);
+            assert_eq!(last_reports.load(Ordering::SeqCst), 1);
+        }
+    }
+}
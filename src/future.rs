@@ -0,0 +1,255 @@
+//! Running adaptive computations from async code without blocking the executor.
+//! The computation runs in the background and completes a one shot future, waking its task.
+//! Dropping the future cancels the computation : all its tasks unwind at their next block.
+use crate::backend;
+use crate::cancellation::{CancellationToken, Cancelled};
+use crossbeam::atomic::AtomicCell;
+use std::future::Future;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+/// What the computation and its future share.
+struct Completion<R> {
+    result: AtomicCell<Option<thread::Result<R>>>,
+    waker: Mutex<Option<Waker>>,
+    token: CancellationToken,
+}
+
+impl<R> Completion<R> {
+    fn complete(&self, result: thread::Result<R>) {
+        self.result.store(Some(result));
+        // pairs with the second check in `poll`: either it sees the result
+        // or we see its waker.
+        if let Some(waker) = self.waker.lock().expect("waker poisoned").take() {
+            waker.wake()
+        }
+    }
+}
+
+/// Future of a computation started with `spawn_adaptive`.
+struct AdaptiveFuture<R> {
+    completion: Arc<Completion<R>>,
+}
+
+impl<R> Future for AdaptiveFuture<R> {
+    type Output = R;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<R> {
+        let completion = &self.completion;
+        let result = completion.result.take().or_else(|| {
+            *completion.waker.lock().expect("waker poisoned") = Some(cx.waker().clone());
+            completion.result.take()
+        });
+        match result {
+            Some(Ok(result)) => Poll::Ready(result),
+            Some(Err(panic)) => resume_unwind(panic),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<R> Drop for AdaptiveFuture<R> {
+    fn drop(&mut self) {
+        self.completion.token.cancel()
+    }
+}
+
+/// Run given computation in the background (in the thread pool)
+/// and return a future of its result.
+/// Dropping the future before completion cancels the computation.
+/// Panics of the computation propagate when polling.
+///
+/// # Example
+///
+/// ```
+/// use rayon_adaptive::prelude::*;
+/// use rayon_adaptive::spawn_adaptive;
+/// # use std::future::Future;
+/// # use std::sync::Arc;
+/// # use std::task::{Context, Poll, Wake};
+/// # struct Unparker(std::thread::Thread);
+/// # impl Wake for Unparker {
+/// #     fn wake(self: Arc<Self>) {
+/// #         self.0.unpark()
+/// #     }
+/// # }
+/// # fn block_on<F: Future>(future: F) -> F::Output {
+/// #     let mut future = Box::pin(future);
+/// #     let waker = Arc::new(Unparker(std::thread::current())).into();
+/// #     let mut cx = Context::from_waker(&waker);
+/// #     loop {
+/// #         match future.as_mut().poll(&mut cx) {
+/// #             Poll::Ready(result) => return result,
+/// #             Poll::Pending => std::thread::park(),
+/// #         }
+/// #     }
+/// # }
+/// let sum = spawn_adaptive(|| (0..1_000_000).into_adapt_iter().sum::<usize>());
+/// // any executor will do
+/// assert_eq!(block_on(sum), 499_999_500_000);
+/// ```
+pub fn spawn_adaptive<R, OP>(op: OP) -> impl Future<Output = R>
+where
+    R: Send + 'static,
+    OP: FnOnce() -> R + Send + 'static,
+{
+    let completion = Arc::new(Completion {
+        result: AtomicCell::new(None),
+        waker: Mutex::new(None),
+        token: CancellationToken::new(),
+    });
+    let computation_completion = completion.clone();
+    backend::spawn(move || {
+        let completion = computation_completion;
+        let result = {
+            let _token = completion.token.enter();
+            catch_unwind(AssertUnwindSafe(op))
+        };
+        match result {
+            // nobody is waiting for us anymore
+            Err(ref payload) if payload.is::<Cancelled>() => (),
+            _ => completion.complete(result),
+        }
+    });
+    AdaptiveFuture { completion }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::spawn_adaptive;
+    use crate::prelude::*;
+    use crate::Policy;
+    use std::future::Future;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+    use std::time::Duration;
+
+    /// Minimal executor : we park until woken up.
+    struct Unparker(Thread);
+
+    impl Wake for Unparker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark()
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Arc::new(Unparker(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => return result,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn spawned_computations_complete() {
+        let futures: Vec<_> = (0..10)
+            .map(|i| {
+                spawn_adaptive(move || {
+                    (0..100_000)
+                        .into_adapt_iter()
+                        .map(|e| e * i)
+                        .with_policy(Policy::Adaptive(10, 100))
+                        .sum::<usize>()
+                })
+            })
+            .collect();
+        for (i, future) in futures.into_iter().enumerate() {
+            assert_eq!(block_on(future), 4_999_950_000 * i);
+        }
+    }
+
+    /// Tell when the computation ends, even by unwinding.
+    struct SetOnDrop(Arc<AtomicBool>);
+
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn dropping_futures_cancels_computations() {
+        for policy in &[
+            Policy::DefaultPolicy,
+            Policy::Join(100),
+            Policy::Adaptive(10, 100),
+        ] {
+            let policy = *policy;
+            let processed = Arc::new(AtomicUsize::new(0));
+            let ended = Arc::new(AtomicBool::new(false));
+            let (computation_processed, end) = (processed.clone(), SetOnDrop(ended.clone()));
+            let future = spawn_adaptive(move || {
+                let _end = end;
+                (0..1_000_000)
+                    .into_adapt_iter()
+                    .with_policy(policy)
+                    .for_each(|_| {
+                        computation_processed.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_micros(10))
+                    })
+            });
+            while processed.load(Ordering::SeqCst) == 0 {
+                thread::yield_now()
+            }
+            drop(future);
+            while !ended.load(Ordering::SeqCst) {
+                thread::yield_now()
+            }
+            assert!(processed.load(Ordering::SeqCst) < 1_000_000);
+        }
+    }
+
+    #[test]
+    fn cancellations_do_not_leak_into_other_computations() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .expect("pool creation failed");
+        for _ in 0..10 {
+            let processed = Arc::new(AtomicUsize::new(0));
+            let computation_processed = processed.clone();
+            let future = pool.install(|| {
+                spawn_adaptive(move || {
+                    (0..1_000_000)
+                        .into_adapt_iter()
+                        .with_policy(Policy::Join(1))
+                        .for_each(|_| {
+                            computation_processed.fetch_add(1, Ordering::SeqCst);
+                            thread::sleep(Duration::from_micros(100))
+                        })
+                })
+            });
+            while processed.load(Ordering::SeqCst) == 0 {
+                thread::yield_now()
+            }
+            // while the cancelled tasks unwind their threads steal ours
+            let sum: usize = thread::scope(|s| {
+                let sum = s.spawn(|| {
+                    pool.install(|| {
+                        (0..10_000)
+                            .into_adapt_iter()
+                            .map(|e| {
+                                thread::sleep(Duration::from_micros(10));
+                                e
+                            })
+                            .with_policy(Policy::Join(1))
+                            .sum()
+                    })
+                });
+                thread::sleep(Duration::from_millis(1));
+                drop(future);
+                sum.join().expect("plain computation failed")
+            });
+            assert_eq!(sum, 49_995_000);
+        }
+    }
+}
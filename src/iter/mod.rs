@@ -1,5 +1,7 @@
 use crate::activated_input::ActivatedInput;
-use crate::folders::{fold::Fold, iterator_fold::AdaptiveIteratorFold};
+use crate::cancellation::{Cancellable, CancellationToken};
+use crate::folders::{fold::Fold, iterator_fold::AdaptiveIteratorFold, Folder};
+use crate::max_block_size;
 use crate::prelude::*;
 use crate::traits::{BlockedOrMore, BlockedPower};
 use std::iter::Empty;
@@ -17,6 +19,7 @@ use self::filter::Filter;
 use crate::policy::ParametrizedInput;
 use std;
 use std::cmp::min;
+use std::io::{self, Write};
 mod collect;
 pub use self::collect::{FromAdaptiveBlockedIterator, FromAdaptiveIndexedIterator};
 pub(crate) mod hash;
@@ -77,8 +80,8 @@ pub trait AdaptiveIndexedIterator: AdaptiveIterator + DivisibleAtIndex {
     }
 }
 
-pub trait AdaptiveIteratorRunner<I: AdaptiveIterator, S: Iterator<Item = usize>>:
-    AdaptiveRunner<I, S>
+pub trait AdaptiveIteratorRunner<'p, I: AdaptiveIterator, S: Iterator<Item = usize>>:
+    AdaptiveRunner<'p, I, S>
 {
     fn find_any<P>(self, predicate: P) -> Option<I::Item>
     where
@@ -86,31 +89,38 @@ pub trait AdaptiveIteratorRunner<I: AdaptiveIterator, S: Iterator<Item = usize>>
         I::Item: Sync + Send,
     {
         let found = AtomicBool::new(false);
+        let max_threads = self.max_threads();
+        let pool = self.pool();
         let (input, policy, sizes) = self.input_policy_sizes();
         let aborting_input = AbortingDivisible {
             real_content: input,
             abort: &found,
         };
-        aborting_input
-            .with_policy(policy)
-            .by_blocks(sizes)
-            .cutting_fold(
-                || None,
-                |f, i| {
-                    if f.is_some() {
-                        f
-                    } else {
-                        let new_f = i.into_iter().find(&predicate);
-                        if new_f.is_some() {
-                            found.store(true, Ordering::Relaxed)
-                        }
-                        new_f
+        ParametrizedInput {
+            input: aborting_input,
+            policy,
+            sizes,
+            max_threads,
+            pool,
+        }
+        .cutting_fold(
+            || None,
+            |f, i| {
+                if f.is_some() {
+                    f
+                } else {
+                    let new_f = i.into_iter().find(&predicate);
+                    if new_f.is_some() {
+                        found.store(true, Ordering::Relaxed)
                     }
-                },
-            )
-            .into_iter()
-            .filter_map(|o| o)
-            .next()
+                    new_f
+                }
+            },
+        )
+        .fitting_in_cache::<I::Item>()
+        .into_iter()
+        .filter_map(|o| o)
+        .next()
     }
 
     /// Find first e in iterator such that predicate(e) is true.
@@ -127,27 +137,34 @@ pub trait AdaptiveIteratorRunner<I: AdaptiveIterator, S: Iterator<Item = usize>>
         P: Fn(&I::Item) -> bool + Sync + Send,
         I::Item: Sync + Send,
     {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
         let (input, policy, sizes) = self.input_policy_sizes();
         let len = input.base_length();
         let base_size = min((len as f64).log(2.0).ceil() as usize, len);
-        input
-            .with_policy(policy)
-            .by_blocks(sizes.chain(powers(base_size)))
-            .partial_fold(
-                || None,
-                |found, i, limit| {
-                    //TODO: nothing is remaining if found.
-                    //should we have options ???
-                    let (todo, remaining) = i.divide_at(limit);
-                    (
-                        found.or_else(|| todo.into_iter().find(&predicate)),
-                        remaining,
-                    )
-                },
-            )
-            .into_iter()
-            .filter_map(|o| o)
-            .next()
+        ParametrizedInput {
+            input,
+            policy,
+            sizes: sizes.chain(powers(base_size)),
+            max_threads,
+            pool,
+        }
+        .partial_fold(
+            || None,
+            |found, i, limit| {
+                //TODO: nothing is remaining if found.
+                //should we have options ???
+                let (todo, remaining) = i.divide_at(limit);
+                (
+                    found.or_else(|| todo.into_iter().find(&predicate)),
+                    remaining,
+                )
+            },
+        )
+        .fitting_in_cache::<I::Item>()
+        .into_iter()
+        .filter_map(|o| o)
+        .next()
     }
     /// Return if any element e in the iterator is such that
     /// predicate(e) is true.
@@ -184,14 +201,23 @@ pub trait AdaptiveIteratorRunner<I: AdaptiveIterator, S: Iterator<Item = usize>>
     where
         P: Fn(I::Item) -> bool + Sync + Send,
     {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
         let (input, policy, sizes) = self.input_policy_sizes();
         let base_size = std::cmp::min(
             (input.base_length() as f64).log(2.0).ceil() as usize,
             input.base_length(),
         );
-        ActivatedInput {
-            input,
-            folder: Fold {
+        let sizes = sizes.chain(powers(base_size)); // this way if empty we take powers
+        activate_in_cache(
+            ParametrizedInput {
+                input,
+                policy,
+                sizes,
+                max_threads,
+                pool,
+            },
+            Fold {
                 identity_op: || true,
                 fold_op: |s: bool, i: I, limit: usize| {
                     let (todo, remaining) = i.divide_at(limit);
@@ -206,10 +232,7 @@ pub trait AdaptiveIteratorRunner<I: AdaptiveIterator, S: Iterator<Item = usize>>
                 },
                 phantom: PhantomData,
             },
-            policy,
-            sizes: sizes.chain(powers(base_size)), // this way if empty we take powers
-            power: PhantomData,
-        }
+        )
         .into_iter()
         .all(|b| b)
     }
@@ -238,10 +261,9 @@ pub trait AdaptiveIteratorRunner<I: AdaptiveIterator, S: Iterator<Item = usize>>
     where
         I::Item: Ord + Send + Sync,
     {
-        let (input, policy, sizes) = self.input_policy_sizes();
-        ActivatedInput {
-            input,
-            folder: Fold {
+        activate_in_cache(
+            self,
+            Fold {
                 identity_op: || None,
                 fold_op: |previous_max, i: I, limit: usize| {
                     let (todo, remaining) = i.divide_at(limit);
@@ -250,20 +272,16 @@ pub trait AdaptiveIteratorRunner<I: AdaptiveIterator, S: Iterator<Item = usize>>
                 },
                 phantom: PhantomData,
             },
-            policy,
-            sizes,
-            power: PhantomData,
-        }
+        )
         .reduce(std::cmp::max)
     }
     fn sum<SUM>(self) -> SUM
     where
         SUM: std::iter::Sum<I::Item> + Send + Sync + std::ops::Add<Output = SUM>,
     {
-        let (input, policy, sizes) = self.input_policy_sizes();
-        ActivatedInput {
-            input,
-            folder: Fold {
+        activate_in_cache(
+            self,
+            Fold {
                 identity_op: || None.into_iter().sum(),
                 fold_op: |s: SUM, i: I, limit: usize| {
                     let (todo, remaining) = i.divide_at(limit);
@@ -272,10 +290,7 @@ pub trait AdaptiveIteratorRunner<I: AdaptiveIterator, S: Iterator<Item = usize>>
                 },
                 phantom: PhantomData,
             },
-            policy,
-            sizes,
-            power: PhantomData,
-        }
+        )
         .reduce(|a, b| a + b)
     }
 
@@ -296,10 +311,9 @@ pub trait AdaptiveIteratorRunner<I: AdaptiveIterator, S: Iterator<Item = usize>>
     where
         OP: Fn(I::Item) + Sync + Send,
     {
-        let (input, policy, sizes) = self.input_policy_sizes();
-        ActivatedInput {
-            input,
-            folder: Fold {
+        activate_in_cache(
+            self,
+            Fold {
                 identity_op: || (),
                 fold_op: |_, i: I, limit: usize| {
                     let (todo, remaining) = i.divide_at(limit);
@@ -308,41 +322,138 @@ pub trait AdaptiveIteratorRunner<I: AdaptiveIterator, S: Iterator<Item = usize>>
                 },
                 phantom: PhantomData,
             },
-            policy,
-            sizes,
-            power: PhantomData,
-        }
+        )
         .reduce(|_, _| ())
     }
 
+    /// Write all elements in order into given writer, formatting each one with `format`.
+    /// The calling thread writes directly while stolen parts buffer their output
+    /// which gets written when retrieved.
+    /// We stop at the first io error and return it.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// use std::io::{self, Write};
+    /// let mut output = Vec::new();
+    /// (0..1_000)
+    ///     .into_adapt_iter()
+    ///     .write_ordered(&mut output, |w, e| writeln!(w, "{}", e))
+    ///     .expect("writing failed");
+    /// let expected: String = (0..1_000).map(|e| format!("{}\n", e)).collect();
+    /// assert_eq!(String::from_utf8(output).unwrap(), expected);
+    /// let failing = (0..1_000).into_adapt_iter().write_ordered(io::sink(), |w, e| {
+    ///     if e == 500 {
+    ///         Err(io::Error::new(io::ErrorKind::Other, "we don't like 500"))
+    ///     } else {
+    ///         writeln!(w, "{}", e)
+    ///     }
+    /// });
+    /// assert!(failing.is_err());
+    /// ```
+    fn write_ordered<W, FMT>(self, writer: W, format: FMT) -> io::Result<()>
+    where
+        W: Write + Send,
+        FMT: Fn(&mut dyn Write, I::Item) -> io::Result<()> + Sync,
+        S: Send,
+    {
+        // on errors we stop everyone
+        fn cancel_on_error<T>(token: &CancellationToken, result: io::Result<T>) -> io::Result<T> {
+            if result.is_err() {
+                token.cancel()
+            }
+            result
+        }
+        let token = CancellationToken::new();
+        let token_ref = &token;
+        let format_ref = &format;
+        activate_in_cache::<_, _, _, BlockedOrMore, _>(
+            self.with_cancellation(token_ref),
+            Fold {
+                identity_op: || Ok(Vec::new()),
+                fold_op: |buffer: io::Result<Vec<u8>>, i: Cancellable<I>, limit: usize| {
+                    let (todo, remaining) = i.divide_at(limit);
+                    let buffer = buffer.and_then(|mut buffer| {
+                        todo.into_iter()
+                            .try_for_each(|e| format_ref(&mut buffer, e))
+                            .map(|_| buffer)
+                    });
+                    (cancel_on_error(token_ref, buffer), remaining)
+                },
+                phantom: PhantomData,
+            },
+        )
+        .helping_partial_fold(
+            Ok(writer),
+            |writer: io::Result<W>, i, limit| {
+                let (todo, remaining) = i.divide_at(limit);
+                let writer = writer.and_then(|mut writer| {
+                    todo.into_iter()
+                        .try_for_each(|e| format_ref(&mut writer, e))
+                        .map(|_| writer)
+                });
+                (cancel_on_error(token_ref, writer), remaining)
+            },
+            |writer, buffer| {
+                let writer = writer.and_then(|mut writer| {
+                    writer.write_all(&buffer?)?;
+                    Ok(writer)
+                });
+                cancel_on_error(token_ref, writer)
+            },
+        )
+        .and_then(|mut writer| writer.flush())
+    }
+
     fn fold<IO, ID, F>(
         self,
         identity: ID,
         fold_op: F,
-    ) -> ActivatedInput<AdaptiveIteratorFold<I, IO, ID, F>, S, BlockedOrMore>
+    ) -> ActivatedInput<'p, AdaptiveIteratorFold<I, IO, ID, F>, S, BlockedOrMore>
     where
         IO: Send + Sync + Clone,
         ID: Fn() -> IO + Sync + Send + Clone,
         F: Fn(IO, I::Item) -> IO + Sync + Send + Clone,
     {
-        let (input, policy, sizes) = self.input_policy_sizes();
-        ActivatedInput {
-            input,
-            folder: AdaptiveIteratorFold {
+        activate_in_cache(
+            self,
+            AdaptiveIteratorFold {
                 identity_op: identity,
                 fold_op,
                 phantom: PhantomData,
             },
-            policy,
-            sizes,
-            power: PhantomData,
-        }
+        )
+    }
+}
+
+/// Activate given folder on an iterator,
+/// capping default block sizes so that blocks of its items fit in a core's cache.
+fn activate_in_cache<'p, I, S, F, P, R>(runner: R, folder: F) -> ActivatedInput<'p, F, S, P>
+where
+    I: AdaptiveIterator,
+    S: Iterator<Item = usize>,
+    F: Folder<Input = I>,
+    R: AdaptiveRunner<'p, I, S>,
+{
+    let max_threads = runner.max_threads();
+    let pool = runner.pool();
+    let (input, policy, sizes) = runner.input_policy_sizes();
+    ActivatedInput {
+        input,
+        folder,
+        policy,
+        sizes,
+        max_threads,
+        pool,
+        cache_block_size: Some(max_block_size::<I::Item>()),
+        power: PhantomData,
     }
 }
 
 /// Specializations of AdaptiveIteratorRunner.
-pub trait AdaptiveIndexedIteratorRunner<I: AdaptiveIndexedIterator, S: Iterator<Item = usize>>:
-    AdaptiveRunner<I, S>
+pub trait AdaptiveIndexedIteratorRunner<'p, I: AdaptiveIndexedIterator, S: Iterator<Item = usize>>:
+    AdaptiveRunner<'p, I, S>
 {
     /// Collect turn an `AdaptiveIterator` into a collection.
     /// As of now it is only implemented for `Vec`.
@@ -366,9 +477,10 @@ pub trait AdaptiveIndexedIteratorRunner<I: AdaptiveIndexedIterator, S: Iterator<
     }
 }
 pub trait AdaptiveBlockedIteratorRunner<
+    'p,
     I: AdaptiveIterator<Power = BlockedPower>,
     S: Iterator<Item = usize>,
->: AdaptiveRunner<I, S>
+>: AdaptiveRunner<'p, I, S>
 {
     /// Collect turn an `AdaptiveIterator` into a collection.
     /// As of now it is only implemented for `Vec`.
@@ -394,23 +506,23 @@ pub trait AdaptiveBlockedIteratorRunner<
     }
 }
 
-impl<I: AdaptiveIterator, S: Iterator<Item = usize>> AdaptiveIteratorRunner<I, S>
-    for ParametrizedInput<I, S>
+impl<'p, I: AdaptiveIterator, S: Iterator<Item = usize>> AdaptiveIteratorRunner<'p, I, S>
+    for ParametrizedInput<'p, I, S>
 {
 }
-impl<I: AdaptiveIterator> AdaptiveIteratorRunner<I, Empty<usize>> for I {}
+impl<'p, I: AdaptiveIterator> AdaptiveIteratorRunner<'p, I, Empty<usize>> for I {}
 
-impl<I: AdaptiveIndexedIterator, S: Iterator<Item = usize>> AdaptiveIndexedIteratorRunner<I, S>
-    for ParametrizedInput<I, S>
+impl<'p, I: AdaptiveIndexedIterator, S: Iterator<Item = usize>>
+    AdaptiveIndexedIteratorRunner<'p, I, S> for ParametrizedInput<'p, I, S>
 {
 }
-impl<I: AdaptiveIndexedIterator> AdaptiveIndexedIteratorRunner<I, Empty<usize>> for I {}
+impl<'p, I: AdaptiveIndexedIterator> AdaptiveIndexedIteratorRunner<'p, I, Empty<usize>> for I {}
 
-impl<I: AdaptiveIterator<Power = BlockedPower>, S: Iterator<Item = usize>>
-    AdaptiveBlockedIteratorRunner<I, S> for ParametrizedInput<I, S>
+impl<'p, I: AdaptiveIterator<Power = BlockedPower>, S: Iterator<Item = usize>>
+    AdaptiveBlockedIteratorRunner<'p, I, S> for ParametrizedInput<'p, I, S>
 {
 }
-impl<I: AdaptiveIterator<Power = BlockedPower>> AdaptiveBlockedIteratorRunner<I, Empty<usize>>
-    for I
+impl<'p, I: AdaptiveIterator<Power = BlockedPower>>
+    AdaptiveBlockedIteratorRunner<'p, I, Empty<usize>> for I
 {
 }
@@ -13,13 +13,27 @@ extern crate smallvec;
 use std::sync::atomic::{AtomicBool, Ordering};
 mod traits;
 pub use crate::traits::*;
+mod backend;
+mod nesting;
+use crate::nesting::DepthGuard;
+mod numa;
+pub use crate::numa::{adaptive_vec_init, first_touch, NumaLayout};
 mod scheduling;
 pub mod utils;
 pub use crate::utils::fuse_slices;
 mod slices;
 pub use crate::slices::{EdibleSlice, EdibleSliceMut};
 mod activated_input;
+mod cancellation;
+pub use crate::cancellation::{Cancellable, CancellationToken, Cancelled};
+mod deadline;
+pub use crate::deadline::{Partial, Timed};
+mod future;
+pub use crate::future::spawn_adaptive;
 mod chunks;
+mod compare;
+mod environment;
+pub use crate::compare::{Comparison, ComparisonResults, Measure};
 pub mod iter;
 pub use crate::iter::hash::{par_elements, par_iter, par_keys};
 pub use crate::iter::iter::Iter;
@@ -28,12 +42,31 @@ pub use crate::iter::zip::Zip;
 
 mod folders;
 pub use crate::folders::Folder;
+#[cfg(any(feature = "stats", feature = "trace"))]
+pub use crate::folders::SchedulingEvent;
 mod policy;
-pub use crate::policy::Policy;
+mod pool;
+pub use crate::policy::{ParsePolicyError, Policy};
 mod atomiclist;
+mod outputs;
 pub mod prelude;
+mod signal;
+#[cfg(feature = "stats")]
+mod stats;
+#[cfg(feature = "stats")]
+pub use crate::stats::{SchedulingStats, WithStats};
 mod smallchannel;
+mod topology;
+pub use crate::topology::{macro_block_size, max_block_size, MachineTopology};
+mod stream;
+mod threads;
+#[cfg(feature = "trace")]
+mod trace;
+mod tune;
 pub use crate::smallchannel::{small_channel, SmallReceiver, SmallSender};
+#[cfg(feature = "trace")]
+pub use crate::trace::{Trace, TraceEvent, TraceEventKind, WithTrace};
+pub use crate::tune::autotune;
 
 mod algorithms;
 pub use crate::algorithms::infix_solvers::*;
@@ -54,8 +87,8 @@ where
     let done = &AtomicBool::new(false);
     let (sender_a, receiver_a) = small_channel();
     let (sender_b, receiver_b) = small_channel();
-    let results = rayon::join(
-        move || {
+    let results = backend::join_context(
+        move |_| {
             let ra = oper_a();
             let we_are_last = done.swap(true, Ordering::SeqCst);
             if we_are_last {
@@ -66,7 +99,8 @@ where
                 None
             }
         },
-        move || {
+        move |c| {
+            let _depth = DepthGuard::task(c.migrated());
             let rb = oper_b();
             let we_are_last = done.swap(true, Ordering::SeqCst);
             if we_are_last {
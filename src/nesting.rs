@@ -0,0 +1,69 @@
+//! Composition of adaptive algorithms.
+//! We prevent fine grain parallelism when coarse grain parallelism is still available
+//! in enclosing computations.
+//! Each task knows how many enclosing levels are currently running a block sequentially.
+//! Computations nested inside such a block only let idle threads steal from them.
+//! Depth is changed through guards so it gets restored even if a panic unwinds the stack.
+use std::cell::Cell;
+
+thread_local!(static SEQUENTIAL_DEPTH: Cell<usize> = const { Cell::new(0) });
+
+/// Return how many enclosing levels are running sequential blocks for the current task.
+pub(crate) fn sequential_depth() -> usize {
+    SEQUENTIAL_DEPTH.with(Cell::get)
+}
+
+/// Set the sequential depth of the current task until dropped.
+pub(crate) struct DepthGuard {
+    previous_depth: usize,
+}
+
+impl DepthGuard {
+    fn set(depth: usize) -> Self {
+        DepthGuard {
+            previous_depth: SEQUENTIAL_DEPTH.with(|d| d.replace(depth)),
+        }
+    }
+    /// We enter a block running sequentially while enclosing levels
+    /// might still have coarse grain parallelism available.
+    pub(crate) fn sequential_block() -> Self {
+        DepthGuard::set(sequential_depth() + 1)
+    }
+    /// We start executing a task.
+    /// Stolen tasks run on threads which were idle so they can use all parallelism.
+    /// Others just keep the depth of the task which created them.
+    pub(crate) fn task(stolen: bool) -> Self {
+        if stolen {
+            DepthGuard::set(0)
+        } else {
+            DepthGuard::set(sequential_depth())
+        }
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        SEQUENTIAL_DEPTH.with(|d| d.set(self.previous_depth))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    #[test]
+    fn nested_computations() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .expect("pool build failed");
+        let sums: Vec<usize> = pool.install(|| {
+            (1..1_000)
+                .into_adapt_iter()
+                .map(|i| (0..i * 10).into_adapt_iter().sum::<usize>())
+                .collect()
+        });
+        let expected: Vec<usize> = (1..1_000).map(|i| (0..i * 10).sum()).collect();
+        assert_eq!(sums, expected);
+    }
+}
@@ -0,0 +1,472 @@
+//! NUMA-aware scheduling.
+//! Threads of pools built from a `NumaLayout` are pinned to cores and know their NUMA node.
+//! Thieves tag their steal requests with their node and, with `Policy::Numa`,
+//! victims only give a smaller share of their input to thieves from other nodes
+//! since the data is likely far away from them.
+//! `first_touch` and `adaptive_vec_init` place memory pages close to the threads which
+//! will later process them.
+use crate::backend;
+use crate::prelude::*;
+use crate::Policy;
+use hwloc::{ObjectType, Topology, CPUBIND_THREAD};
+use rayon_core::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use std::cell::{Cell, RefCell};
+use std::cmp::max;
+use std::iter::repeat_n;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use std::sync::Mutex;
+
+thread_local!(static NUMA_NODE: Cell<Option<usize>> = const { Cell::new(None) });
+thread_local!(static NUMA_LAYOUT: RefCell<Option<NumaLayout>> = const { RefCell::new(None) });
+
+/// Return the NUMA node of the current thread (if it belongs to a pool built from a layout).
+pub(crate) fn current_node() -> Option<usize> {
+    NUMA_NODE.with(Cell::get)
+}
+
+/// Return the layout of the pool the current thread belongs to (if built from one).
+#[cfg(feature = "scoped-threads")]
+pub(crate) fn current_layout() -> Option<NumaLayout> {
+    NUMA_LAYOUT.with(|layout| layout.borrow().clone())
+}
+
+/// Give the current thread the NUMA node of given thread of a pool built from given layout
+/// (threads do not get pinned). The previous node comes back when the guard is dropped.
+#[cfg(feature = "scoped-threads")]
+pub(crate) fn enter_node(layout: &NumaLayout, thread_index: usize) -> NodeGuard {
+    let node = Some(layout.node_of_thread(thread_index));
+    NodeGuard {
+        previous: NUMA_NODE.with(|current| current.replace(node)),
+    }
+}
+
+/// Restore the previous NUMA node of the thread when dropped.
+#[cfg(feature = "scoped-threads")]
+pub(crate) struct NodeGuard {
+    previous: Option<usize>,
+}
+
+#[cfg(feature = "scoped-threads")]
+impl Drop for NodeGuard {
+    fn drop(&mut self) {
+        NUMA_NODE.with(|node| node.set(self.previous))
+    }
+}
+
+/// Steal requests states (see `steal_request`).
+pub(crate) const NOT_STOLEN: usize = 0;
+const UNKNOWN_NODE: usize = 1;
+/// The thief found no place in the computation (see `with_max_threads`).
+pub(crate) const REFUSED: usize = usize::MAX;
+
+/// Return the steal request of the current thread: its tagged node.
+pub(crate) fn steal_request() -> usize {
+    current_node().map_or(UNKNOWN_NODE, |node| node + 2)
+}
+
+/// Return if given steal request comes from another NUMA node than ours.
+pub(crate) fn is_remote(request: usize) -> bool {
+    match (request, current_node()) {
+        (NOT_STOLEN, _) | (UNKNOWN_NODE, _) | (REFUSED, _) | (_, None) => false,
+        (request, Some(node)) => request - 2 != node,
+    }
+}
+
+/// Cores and NUMA nodes of a machine, real or simulated.
+///
+/// # Example
+///
+/// ```
+/// use rayon_adaptive::prelude::*;
+/// use rayon_adaptive::{NumaLayout, Policy};
+/// // two sockets of four cores each
+/// let pool = NumaLayout::simulated(2, 4)
+///     .thread_pool(8)
+///     .expect("failed building pool");
+/// let sum = pool.install(|| {
+///     (0..100_000)
+///         .into_adapt_iter()
+///         .with_policy(Policy::Numa(100, 10_000))
+///         .fold(|| 0, |s, e| s + e)
+///         .reduce(|a, b| a + b)
+/// });
+/// assert_eq!(sum, 4_999_950_000);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumaLayout {
+    /// NUMA node of each core.
+    cores_nodes: Vec<usize>,
+    /// Should threads get pinned to cores (only on real machines).
+    pinned: bool,
+}
+
+impl NumaLayout {
+    /// Return the layout of the machine we run on, as reported by hwloc.
+    pub fn current() -> Self {
+        let topology = Topology::new();
+        let cores_nodes: Vec<usize> = topology
+            .objects_with_type(&ObjectType::Core)
+            .map(|cores| {
+                cores
+                    .iter()
+                    .map(|core| {
+                        // on non-NUMA machines nodesets are full : everyone is on node 0
+                        core.nodeset()
+                            .map_or(0, |nodeset| max(nodeset.first(), 0) as usize)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        if cores_nodes.is_empty() {
+            NumaLayout::simulated(1, backend::current_num_threads())
+        } else {
+            NumaLayout {
+                cores_nodes,
+                pinned: true,
+            }
+        }
+    }
+    /// Simulate a machine with given number of nodes, each one with given number of cores.
+    /// Threads are not pinned.
+    pub fn simulated(nodes: usize, cores_per_node: usize) -> Self {
+        assert!(nodes * cores_per_node > 0, "we need at least one core");
+        NumaLayout {
+            cores_nodes: (0..nodes)
+                .flat_map(|node| repeat_n(node, cores_per_node))
+                .collect(),
+            pinned: false,
+        }
+    }
+    /// Return the number of cores.
+    pub fn cores(&self) -> usize {
+        self.cores_nodes.len()
+    }
+    /// Return the number of NUMA nodes.
+    pub fn nodes(&self) -> usize {
+        self.cores_nodes.iter().max().map_or(0, |&node| node + 1)
+    }
+    /// Return on which NUMA node the given thread runs.
+    /// Thread `i` runs on core `i` (modulo the number of cores).
+    pub fn node_of_thread(&self, thread_index: usize) -> usize {
+        self.cores_nodes[thread_index % self.cores()]
+    }
+    /// Build a thread pool whose threads know their NUMA node.
+    /// On real machines thread `i` gets pinned to core `i` (modulo the number of cores).
+    pub fn thread_pool(&self, num_threads: usize) -> Result<ThreadPool, ThreadPoolBuildError> {
+        let layout = self.clone();
+        let topology = if self.pinned {
+            Some(Mutex::new(Topology::new()))
+        } else {
+            None
+        };
+        ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .start_handler(move |thread_index| {
+                if let Some(topology) = &topology {
+                    pin_thread(topology, thread_index % layout.cores())
+                }
+                NUMA_NODE.with(|node| node.set(Some(layout.node_of_thread(thread_index))));
+                NUMA_LAYOUT.with(|current| *current.borrow_mut() = Some(layout.clone()))
+            })
+            .build()
+    }
+}
+
+/// Bind current thread to given core.
+/// Failing is not an error : we just lose some locality.
+fn pin_thread(topology: &Mutex<Topology>, core: usize) {
+    let mut topology = topology.lock().expect("poisoned topology");
+    let cpuset = topology
+        .objects_with_type(&ObjectType::Core)
+        .ok()
+        .and_then(|cores| cores.get(core).and_then(|core| core.cpuset()));
+    if let Some(mut cpuset) = cpuset {
+        cpuset.singlify();
+        let _ = topology.set_cpubind(cpuset, CPUBIND_THREAD);
+    }
+}
+
+/// Touch all elements of given slice in parallel, splitting it like `policy` does.
+/// Operating systems allocate pages on the NUMA node of the thread writing them first
+/// so call this on freshly allocated memory (like `vec![0; n]`), before the computation.
+/// Pages then end up close to the threads processing them, as long as the computation
+/// uses the same policy in the same pool. Content is left unchanged.
+///
+/// # Example
+///
+/// ```
+/// use rayon_adaptive::prelude::*;
+/// use rayon_adaptive::{first_touch, Policy};
+/// let mut v = vec![1u64; 100_000];
+/// first_touch(&mut v, Policy::Join(1_000));
+/// v.as_mut_slice()
+///     .into_adapt_iter()
+///     .with_policy(Policy::Join(1_000))
+///     .for_each(|e| *e *= 2);
+/// assert!(v.iter().all(|&e| e == 2));
+/// ```
+pub fn first_touch<T: Send + Sync>(slice: &mut [T], policy: Policy) {
+    slice
+        .into_adapt_iter()
+        .with_policy(policy)
+        .for_each(|e| unsafe {
+            // we write back what we read : only the page fault matters
+            let e: *mut T = e;
+            ptr::write_volatile(e, ptr::read_volatile(e))
+        })
+}
+
+/// Allocate a vector of given length, initialising element `i` with `init(i)` in parallel.
+/// Memory gets split like `policy` does so that (see `first_touch`) pages end up close
+/// to the threads processing them if the computation uses the same policy.
+///
+/// # Example
+///
+/// ```
+/// use rayon_adaptive::prelude::*;
+/// use rayon_adaptive::{adaptive_vec_init, Policy};
+/// let v = adaptive_vec_init(100_000, Policy::Join(1_000), |i| i as u64);
+/// let sum = v
+///     .into_adapt_iter()
+///     .with_policy(Policy::Join(1_000))
+///     .fold(|| 0, |s, e| s + e)
+///     .reduce(|a, b| a + b);
+/// assert_eq!(sum, 4_999_950_000);
+/// ```
+pub fn adaptive_vec_init<T, F>(len: usize, policy: Policy, init: F) -> Vec<T>
+where
+    T: Send + Sync,
+    F: Fn(usize) -> T + Sync + Send,
+{
+    let mut vector = Vec::with_capacity(len);
+    let slots: &mut [MaybeUninit<T>] = &mut vector.spare_capacity_mut()[..len];
+    let initialized = slots
+        .into_adapt_iter()
+        .zip((0..len).into_adapt_iter())
+        .with_policy(policy)
+        .partial_fold(Initialized::new, |mut initialized, slots, limit| {
+            let (todo, remaining) = slots.divide_at(limit);
+            for (slot, index) in todo {
+                let value = init(index);
+                initialized.push(slot.write(value));
+            }
+            (initialized, remaining)
+        })
+        .reduce(Initialized::fuse);
+    assert_eq!(initialized.len, len);
+    // everything is written : the vector now owns the elements
+    mem::forget(initialized);
+    unsafe {
+        vector.set_len(len);
+    }
+    vector
+}
+
+/// Contiguous initialized elements of a vector being built.
+/// If we panic before the end, dropping it drops these elements (and only them).
+struct Initialized<T> {
+    start: *mut T,
+    len: usize,
+}
+
+// we own the elements behind the pointer
+unsafe impl<T: Send> Send for Initialized<T> {}
+unsafe impl<T: Sync> Sync for Initialized<T> {}
+
+impl<T> Initialized<T> {
+    fn new() -> Self {
+        Initialized {
+            start: ptr::null_mut(),
+            len: 0,
+        }
+    }
+    /// Take ownership of the element just written after all the ones we already have.
+    fn push(&mut self, element: &mut T) {
+        if self.len == 0 {
+            self.start = element;
+        }
+        debug_assert_eq!(unsafe { self.start.add(self.len) }, element as *mut T);
+        self.len += 1;
+    }
+    /// Reduce two neighbouring groups of elements.
+    fn fuse(mut self, mut other: Self) -> Self {
+        if self.len == 0 {
+            return other;
+        }
+        if other.len != 0 {
+            assert_eq!(unsafe { self.start.add(self.len) }, other.start);
+            self.len += other.len;
+            other.len = 0;
+        }
+        self
+    }
+}
+
+impl<T> Drop for Initialized<T> {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.start, self.len)) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adaptive_vec_init, current_node, NumaLayout};
+    use crate::backend;
+    use crate::folders::SchedulingEvent;
+    use crate::prelude::*;
+    use crate::{Folder, Policy};
+    use std::ops::Range;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Panic payload of computations failing on purpose.
+    struct FailingOnPurpose;
+
+    /// Count how many of us are alive.
+    #[derive(Debug)]
+    struct Counted<'a>(&'a AtomicUsize);
+
+    impl<'a> Counted<'a> {
+        fn new(alive: &'a AtomicUsize) -> Self {
+            alive.fetch_add(1, Ordering::SeqCst);
+            Counted(alive)
+        }
+    }
+
+    impl<'a> Drop for Counted<'a> {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn panicking_vec_inits_only_drop_initialized_elements() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .expect("pool build failed");
+        let alive = AtomicUsize::new(0);
+        for &policy in &[
+            Policy::Join(100),
+            Policy::Adaptive(10, 1_000),
+            Policy::DefaultPolicy,
+        ] {
+            let v = pool.install(|| adaptive_vec_init(10_000, policy, |_| Counted::new(&alive)));
+            assert_eq!(alive.load(Ordering::SeqCst), 10_000);
+            drop(v);
+            assert_eq!(alive.load(Ordering::SeqCst), 0);
+            let failed = catch_unwind(AssertUnwindSafe(|| {
+                pool.install(|| {
+                    adaptive_vec_init(10_000, policy, |i| {
+                        if i == 5_000 {
+                            std::panic::panic_any(FailingOnPurpose)
+                        }
+                        Counted::new(&alive)
+                    })
+                })
+            }));
+            assert!(failed.expect_err("we should fail").is::<FailingOnPurpose>());
+            assert_eq!(alive.load(Ordering::SeqCst), 0);
+        }
+        assert!(adaptive_vec_init(0, Policy::DefaultPolicy, |_| Counted::new(&alive)).is_empty());
+    }
+
+    /// Where each element got folded (element, thread, node).
+    type Locations = Vec<(usize, usize, Option<usize>)>;
+
+    /// Fold elements slowly (so that thieves get a chance), remembering where each one
+    /// got folded and how much each node gave to its thieves.
+    struct StealsRecorder<'a> {
+        // victim's node, elements given, remaining elements before giving, remote thief
+        answers: &'a Mutex<Vec<(Option<usize>, usize, usize, bool)>>,
+    }
+
+    impl<'a> Folder for StealsRecorder<'a> {
+        type Input = Range<usize>;
+        type IntermediateOutput = Locations;
+        type Output = Locations;
+        fn identity(&self) -> Locations {
+            Vec::new()
+        }
+        fn fold(
+            &self,
+            mut v: Locations,
+            r: Range<usize>,
+            limit: usize,
+        ) -> (Locations, Range<usize>) {
+            let (todo, remaining) = r.divide_at(limit);
+            thread::sleep(Duration::from_micros(10));
+            let thread = backend::current_thread_index().unwrap();
+            v.extend(todo.map(|e| (e, thread, current_node())));
+            (v, remaining)
+        }
+        fn to_output(&self, v: Locations, _remaining: Range<usize>) -> Locations {
+            v
+        }
+        fn record(&self, event: SchedulingEvent) {
+            if let SchedulingEvent::StealAnswer {
+                given,
+                kept,
+                remote,
+            } = event
+            {
+                self.answers
+                    .lock()
+                    .unwrap()
+                    .push((current_node(), given, given + kept, remote))
+            }
+        }
+    }
+
+    #[test]
+    fn numa_stealing_on_simulated_topologies() {
+        let expected: Vec<usize> = (0..100_000).collect();
+        let mut remote_steals = 0;
+        for &(nodes, cores_per_node) in &[(1, 4), (2, 2), (4, 1), (2, 3)] {
+            let layout = NumaLayout::simulated(nodes, cores_per_node);
+            assert_eq!(layout.nodes(), nodes);
+            let pool = layout.thread_pool(4).expect("pool build failed");
+            let answers = Mutex::new(Vec::new());
+            let elements_locations = pool.install(|| {
+                (0..100_000)
+                    .with_policy(Policy::Numa(10, 100))
+                    .with_folder(StealsRecorder { answers: &answers })
+                    .reduce(|mut v1, v2| {
+                        v1.extend(v2);
+                        v1
+                    })
+            });
+            let elements: Vec<usize> = elements_locations.iter().map(|&(e, _, _)| e).collect();
+            assert_eq!(elements, expected);
+            assert!(elements_locations
+                .iter()
+                .all(|&(_, thread, node)| node == Some(layout.node_of_thread(thread))));
+            // remote thieves get a quarter of what is left, local ones get half of it
+            let answers = answers.into_inner().unwrap();
+            for node in 0..nodes {
+                let node_answers = answers.iter().filter(|answer| answer.0 == Some(node));
+                let (remote, local): (Vec<_>, Vec<_>) = node_answers.partition(|answer| answer.3);
+                assert!(remote
+                    .iter()
+                    .all(|&&(_, given, total, _)| 4 * given <= total + 3));
+                assert!(local
+                    .iter()
+                    .all(|&&(_, given, total, _)| 2 * given >= total));
+                if nodes == 1 {
+                    assert!(remote.is_empty());
+                }
+                if cores_per_node == 1 {
+                    assert!(local.is_empty());
+                }
+                remote_steals += remote.len();
+            }
+        }
+        assert!(remote_steals > 0);
+    }
+}
@@ -0,0 +1,144 @@
+//! Gathering folders' outputs in order.
+//! Each leaf task produces one output. Outputs are stored inline
+//! so we only allocate when a reduction gathers more than a few of them.
+//! Past that point inline chunks get linked together so that
+//! each reduction stays in constant time.
+//! Few outputs reduced with linked chunks fill the chunk at the boundary first.
+use crate::folders::Map;
+use crate::Folder;
+use smallvec::SmallVec;
+use std::collections::{linked_list, LinkedList};
+
+/// How many outputs we store without allocating.
+const INLINE_OUTPUTS: usize = 4;
+
+type Chunk<T> = SmallVec<[T; INLINE_OUTPUTS]>;
+
+/// Outputs of a computation, in order.
+pub enum Outputs<T> {
+    /// Few outputs, stored inline.
+    Inline(Chunk<T>),
+    /// Chunks of outputs, spliced in constant time.
+    Chunks(LinkedList<Chunk<T>>),
+}
+
+impl<T> Outputs<T> {
+    pub(crate) fn new() -> Self {
+        Outputs::Inline(SmallVec::new())
+    }
+}
+
+impl<T> IntoIterator for Outputs<T> {
+    type Item = T;
+    type IntoIter = OutputsIter<T>;
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            Outputs::Inline(chunk) => OutputsIter {
+                current_chunk: chunk.into_iter(),
+                chunks: LinkedList::new().into_iter(),
+            },
+            Outputs::Chunks(mut chunks) => OutputsIter {
+                current_chunk: chunks.pop_front().unwrap_or_default().into_iter(),
+                chunks: chunks.into_iter(),
+            },
+        }
+    }
+}
+
+/// Iterator on gathered outputs.
+pub struct OutputsIter<T> {
+    current_chunk: smallvec::IntoIter<[T; INLINE_OUTPUTS]>,
+    chunks: linked_list::IntoIter<Chunk<T>>,
+}
+
+impl<T> Iterator for OutputsIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        loop {
+            if let Some(output) = self.current_chunk.next() {
+                return Some(output);
+            }
+            self.current_chunk = self.chunks.next()?.into_iter();
+        }
+    }
+}
+
+/// Folder gathering outputs of another one.
+pub(crate) type GatheringFolder<F> = Map<
+    F,
+    Outputs<<F as Folder>::Output>,
+    fn(<F as Folder>::Output) -> Outputs<<F as Folder>::Output>,
+>;
+
+fn single_output<T>(output: T) -> Outputs<T> {
+    Outputs::Inline(smallvec![output])
+}
+
+/// Turn a folder into one gathering all its outputs in order.
+pub(crate) fn gathering<F: Folder>(folder: F) -> GatheringFolder<F> {
+    folder.map(single_output as fn(F::Output) -> Outputs<F::Output>)
+}
+
+/// Reduction of gathered outputs.
+/// We never move more than a chunk.
+pub(crate) fn concatenate<T>(left: Outputs<T>, right: Outputs<T>) -> Outputs<T> {
+    match (left, right) {
+        (Outputs::Inline(mut left), Outputs::Inline(right)) => {
+            if left.len() + right.len() <= INLINE_OUTPUTS {
+                left.extend(right);
+                Outputs::Inline(left)
+            } else {
+                let mut chunks = LinkedList::new();
+                chunks.push_back(left);
+                chunks.push_back(right);
+                Outputs::Chunks(chunks)
+            }
+        }
+        (Outputs::Chunks(mut left), Outputs::Inline(right)) => {
+            match left.back_mut() {
+                Some(last) if last.len() + right.len() <= INLINE_OUTPUTS => last.extend(right),
+                _ => left.push_back(right),
+            }
+            Outputs::Chunks(left)
+        }
+        (Outputs::Inline(left), Outputs::Chunks(mut right)) => {
+            match right.front_mut() {
+                Some(first) if first.len() + left.len() <= INLINE_OUTPUTS => {
+                    first.insert_many(0, left)
+                }
+                _ => right.push_front(left),
+            }
+            Outputs::Chunks(right)
+        }
+        (Outputs::Chunks(mut left), Outputs::Chunks(mut right)) => {
+            left.append(&mut right);
+            Outputs::Chunks(left)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{concatenate, single_output, Outputs, INLINE_OUTPUTS};
+
+    fn chunks_count<T>(outputs: &Outputs<T>) -> usize {
+        match outputs {
+            Outputs::Inline(_) => 1,
+            Outputs::Chunks(chunks) => chunks.len(),
+        }
+    }
+
+    #[test]
+    fn leaves_fill_boundary_chunks() {
+        let from_left = (1..100).fold(single_output(0), |outputs, output| {
+            concatenate(outputs, single_output(output))
+        });
+        let from_right = (0..99).rev().fold(single_output(99), |outputs, output| {
+            concatenate(single_output(output), outputs)
+        });
+        for outputs in vec![from_left, from_right] {
+            assert_eq!(chunks_count(&outputs), 100 / INLINE_OUTPUTS);
+            assert!(outputs.into_iter().eq(0..100));
+        }
+    }
+}
@@ -1,13 +1,23 @@
 use crate::activated_input::ActivatedInput;
+use crate::cancellation::{Cancellable, CancellationToken};
+use crate::deadline::Timed;
+use crate::environment::environment_policy;
 /// All scheduling available scheduling policies.
 use crate::folders::{cutting_fold::CuttingFold, fold::Fold, work_fold::WorkFold, Folder};
-use crate::scheduling::schedule;
+use crate::scheduling::{schedule, SchedulingParameters};
 use crate::traits::{BasicPower, BlockedOrMore};
 use crate::{Divisible, DivisibleIntoBlocks};
+use rayon_core::ThreadPool;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
 use std::iter::{empty, once, Empty};
 use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub enum Policy {
     /// Adaptive scheduling policy with dynamic block sizes.
     DefaultPolicy,
@@ -24,6 +34,12 @@ pub enum Policy {
     Adaptive(usize, usize),
     /// Mirrors the rayon join context.
     Rayon,
+    /// Like `Adaptive` but thieves from other NUMA nodes only get a quarter
+    /// of the remaining input (see `NumaLayout`).
+    Numa(usize, usize),
+    /// Use the policy saved by `autotune` under given name
+    /// (or the default policy if there is none).
+    Tuned(&'static str),
 }
 
 impl Default for Policy {
@@ -32,12 +48,100 @@ impl Default for Policy {
     }
 }
 
+/// Policies display as `default`, `seq`, `join:1000`, `join_context:1000`, `depjoin:1000`,
+/// `adaptive:100:10000`, `rayon`, `numa:100:10000` or `tuned:name`.
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Policy::DefaultPolicy => write!(f, "default"),
+            Policy::Sequential => write!(f, "seq"),
+            Policy::Join(block_size) => write!(f, "join:{}", block_size),
+            Policy::JoinContext(block_size) => write!(f, "join_context:{}", block_size),
+            Policy::DepJoin(block_size) => write!(f, "depjoin:{}", block_size),
+            Policy::Adaptive(min_size, max_size) => write!(f, "adaptive:{}:{}", min_size, max_size),
+            Policy::Rayon => write!(f, "rayon"),
+            Policy::Numa(min_size, max_size) => write!(f, "numa:{}:{}", min_size, max_size),
+            Policy::Tuned(name) => write!(f, "tuned:{}", name),
+        }
+    }
+}
+
+/// Error returned when parsing an invalid policy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsePolicyError(String);
+
+impl fmt::Display for ParsePolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid scheduling policy: \"{}\"", self.0)
+    }
+}
+
+impl Error for ParsePolicyError {}
+
+/// Parse back displayed policies (`sequential` is also accepted).
+/// Since tuned policies require static names, names get leaked
+/// (only once for each different name).
+///
+/// # Example
+///
+/// ```
+/// use rayon_adaptive::Policy;
+/// let policy: Policy = "adaptive:100:10000".parse().expect("invalid policy");
+/// assert_eq!(policy.to_string(), "adaptive:100:10000");
+/// assert!("join:many".parse::<Policy>().is_err());
+/// ```
+impl FromStr for Policy {
+    type Err = ParsePolicyError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let error = || ParsePolicyError(text.to_owned());
+        let trimmed = text.trim();
+        const TUNED_PREFIX: &str = "tuned:";
+        if trimmed.starts_with(TUNED_PREFIX) && trimmed.len() > TUNED_PREFIX.len() {
+            return Ok(Policy::Tuned(intern(&trimmed[TUNED_PREFIX.len()..])));
+        }
+        let mut fields = trimmed.split(':');
+        let kind = fields.next().ok_or_else(error)?;
+        let sizes = fields
+            .map(|size| size.parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|_| error())?;
+        match (kind, sizes.as_slice()) {
+            ("default", []) => Ok(Policy::DefaultPolicy),
+            ("seq", []) | ("sequential", []) => Ok(Policy::Sequential),
+            ("join", &[block_size]) => Ok(Policy::Join(block_size)),
+            ("join_context", &[block_size]) => Ok(Policy::JoinContext(block_size)),
+            ("depjoin", &[block_size]) => Ok(Policy::DepJoin(block_size)),
+            ("adaptive", &[min_size, max_size]) => Ok(Policy::Adaptive(min_size, max_size)),
+            ("rayon", []) => Ok(Policy::Rayon),
+            ("numa", &[min_size, max_size]) => Ok(Policy::Numa(min_size, max_size)),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// Return a static copy of given name, leaking it only the first time.
+fn intern(name: &str) -> &'static str {
+    static NAMES: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+    let mut names = NAMES.lock().expect("poisoned policy names");
+    let names = names.get_or_insert_with(HashSet::new);
+    match names.get(name) {
+        Some(interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.to_owned().into_boxed_str());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+
 /// We can assign a scheduling policy to any `Divisible input`.
 /// We obtain this structure holding policy and input together.
-pub struct ParametrizedInput<I: Divisible, S: Iterator<Item = usize>> {
+pub struct ParametrizedInput<'p, I: Divisible, S: Iterator<Item = usize>> {
     pub(crate) input: I,
     pub(crate) policy: Policy,
     pub(crate) sizes: S,
+    pub(crate) max_threads: Option<usize>,
+    pub(crate) pool: Option<&'p ThreadPool>,
 }
 
 /********************************************************************************/
@@ -45,22 +149,142 @@ pub struct ParametrizedInput<I: Divisible, S: Iterator<Item = usize>> {
 /********************************************************************************/
 
 /// Abstract between Input and ParametrizedInput in order to avoid duplicated code.
-pub trait AdaptiveRunner<I: Divisible, S: Iterator<Item = usize>>: Sized {
+pub trait AdaptiveRunner<'p, I: Divisible, S: Iterator<Item = usize>>: Sized {
     /// Return input's base length.
     /// Useful for computing blocks sizes.
     fn input_length(&self) -> usize;
     /// Return input, policy and sizes iterator.
     fn input_policy_sizes(self) -> (I, Policy, S);
+    /// Return how many threads can work on us at once (the whole pool if none).
+    fn max_threads(&self) -> Option<usize> {
+        None
+    }
+    /// Return the thread pool we run on (the current one if none).
+    fn pool(&self) -> Option<&'p ThreadPool> {
+        None
+    }
+    /// Attach a `CancellationToken`.
+    /// Once cancelled, workers stop taking new blocks and we return what was computed so far.
+    fn with_cancellation<'a>(
+        self,
+        token: &'a CancellationToken,
+    ) -> ParametrizedInput<'p, Cancellable<'a, I>, S> {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
+        let (input, policy, sizes) = self.input_policy_sizes();
+        ParametrizedInput {
+            input: Cancellable { input, token },
+            policy,
+            sizes,
+            max_threads,
+            pool,
+        }
+    }
+    /// Stop taking new blocks once given deadline is passed.
+    /// Use `reduce_until_deadline` to know which parts of the input got processed.
+    fn with_deadline(self, deadline: Instant) -> ParametrizedInput<'p, Timed<I>, S> {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
+        let (input, policy, sizes) = self.input_policy_sizes();
+        ParametrizedInput {
+            input: Timed::new(input, deadline),
+            policy,
+            sizes,
+            max_threads,
+            pool,
+        }
+    }
+    /// Run on given thread pool instead of the current one.
+    /// Blocks get sized from its number of threads.
+    /// With the `scoped-threads` feature, only this number of threads is used.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// use rayon_adaptive::Policy;
+    /// let pool = rayon::ThreadPoolBuilder::new()
+    ///     .num_threads(2)
+    ///     .build()
+    ///     .expect("failed building pool");
+    /// let sum = (0..100_000)
+    ///     .into_adapt_iter()
+    ///     .with_policy(Policy::Adaptive(100, 1_000))
+    ///     .on_pool(&pool)
+    ///     .fold(|| 0, |s, e| s + e)
+    ///     .reduce(|a, b| a + b);
+    /// assert_eq!(sum, 4_999_950_000);
+    /// ```
+    fn on_pool(self, pool: &'p ThreadPool) -> ParametrizedInput<'p, I, S> {
+        let max_threads = self.max_threads();
+        let (input, policy, sizes) = self.input_policy_sizes();
+        ParametrizedInput {
+            input,
+            policy,
+            sizes,
+            max_threads,
+            pool: Some(pool),
+        }
+    }
+    /// Name this call site.
+    /// If we use the default policy, the `RAYON_ADAPTIVE_POLICY` environment variable
+    /// can then override it for us only (for example with `sort=adaptive:100:10000`).
+    fn named(self, name: &str) -> ParametrizedInput<'p, I, S> {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
+        let (input, policy, sizes) = self.input_policy_sizes();
+        let policy = match policy {
+            Policy::DefaultPolicy => environment_policy(Some(name)).unwrap_or(policy),
+            _ => policy,
+        };
+        ParametrizedInput {
+            input,
+            policy,
+            sizes,
+            max_threads,
+            pool,
+        }
+    }
+    /// Let at most `max_threads` threads of the pool work on us at once,
+    /// leaving the others free for other tasks.
+    /// Block sizes are computed for this number of threads.
+    /// Only adaptive policies and helping folds refuse thieves beyond the limit.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// let sum = (0..100_000)
+    ///     .into_adapt_iter()
+    ///     .with_max_threads(2)
+    ///     .fold(|| 0, |s, e| s + e)
+    ///     .reduce(|a, b| a + b);
+    /// assert_eq!(sum, 4_999_950_000);
+    /// ```
+    fn with_max_threads(self, max_threads: usize) -> ParametrizedInput<'p, I, S> {
+        assert!(max_threads > 0, "we need at least one thread");
+        let pool = self.pool();
+        let (input, policy, sizes) = self.input_policy_sizes();
+        ParametrizedInput {
+            input,
+            policy,
+            sizes,
+            max_threads: Some(max_threads),
+            pool,
+        }
+    }
 }
 
 /// The stuff everyone can do.
-pub trait AllAdaptiveRunner<I: Divisible, S: Iterator<Item = usize>, P>:
-    AdaptiveRunner<I, S>
+pub trait AllAdaptiveRunner<'p, I: Divisible, S: Iterator<Item = usize>, P>:
+    AdaptiveRunner<'p, I, S>
 {
     fn work<WF: Fn(I, usize) -> I + Sync>(
         self,
         work_function: WF,
-    ) -> ActivatedInput<WorkFold<I, WF>, S, P> {
+    ) -> ActivatedInput<'p, WorkFold<I, WF>, S, P> {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
         let (input, policy, sizes) = self.input_policy_sizes();
         let folder = WorkFold {
             work_function,
@@ -71,6 +295,9 @@ pub trait AllAdaptiveRunner<I: Divisible, S: Iterator<Item = usize>, P>:
             folder,
             policy,
             sizes,
+            max_threads,
+            pool,
+            cache_block_size: None,
             power: PhantomData,
         }
     }
@@ -78,12 +305,14 @@ pub trait AllAdaptiveRunner<I: Divisible, S: Iterator<Item = usize>, P>:
         self,
         identity: ID,
         fold_op: F,
-    ) -> ActivatedInput<Fold<I, O, ID, F>, S, P>
+    ) -> ActivatedInput<'p, Fold<I, O, ID, F>, S, P>
     where
         O: Send + Sync,
         ID: Fn() -> O + Sync,
         F: Fn(O, I, usize) -> (O, I) + Sync,
     {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
         let (input, policy, sizes) = self.input_policy_sizes();
         let folder = Fold {
             identity_op: identity,
@@ -95,6 +324,55 @@ pub trait AllAdaptiveRunner<I: Divisible, S: Iterator<Item = usize>, P>:
             folder,
             policy,
             sizes,
+            max_threads,
+            pool,
+            cache_block_size: None,
+            power: PhantomData,
+        }
+    }
+    /// Fold with a user-defined folder.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// use rayon_adaptive::Folder;
+    /// use std::ops::Range;
+    /// // sum each part, telling where it stopped
+    /// struct SumUntil;
+    /// impl Folder for SumUntil {
+    ///     type Input = Range<usize>;
+    ///     type IntermediateOutput = usize;
+    ///     type Output = (usize, usize);
+    ///     fn identity(&self) -> usize {
+    ///         0
+    ///     }
+    ///     fn fold(&self, sum: usize, r: Range<usize>, limit: usize) -> (usize, Range<usize>) {
+    ///         let (todo, remaining) = r.divide_at(limit);
+    ///         (sum + todo.sum::<usize>(), remaining)
+    ///     }
+    ///     fn to_output(&self, sum: usize, remaining: Range<usize>) -> (usize, usize) {
+    ///         (sum, remaining.start)
+    ///     }
+    /// }
+    /// let (sum, end) = (0..100_000)
+    ///     .with_folder(SumUntil)
+    ///     .reduce(|(s1, e1), (s2, e2)| (s1 + s2, e1.max(e2)));
+    /// assert_eq!(sum, 4_999_950_000);
+    /// assert_eq!(end, 100_000);
+    /// ```
+    fn with_folder<F: Folder<Input = I>>(self, folder: F) -> ActivatedInput<'p, F, S, P> {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
+        let (input, policy, sizes) = self.input_policy_sizes();
+        ActivatedInput {
+            input,
+            folder,
+            policy,
+            sizes,
+            max_threads,
+            pool,
+            cache_block_size: None,
             power: PhantomData,
         }
     }
@@ -107,19 +385,21 @@ pub trait AllAdaptiveRunner<I: Divisible, S: Iterator<Item = usize>, P>:
 }
 
 /// The stuff you can only do if at least DivisibleIntoBlocks
-pub trait BlockAdaptiveRunner<I: DivisibleIntoBlocks, S: Iterator<Item = usize>>:
-    AdaptiveRunner<I, S>
+pub trait BlockAdaptiveRunner<'p, I: DivisibleIntoBlocks, S: Iterator<Item = usize>>:
+    AdaptiveRunner<'p, I, S>
 {
     fn cutting_fold<O, ID, F>(
         self,
         identity: ID,
         fold_op: F,
-    ) -> ActivatedInput<CuttingFold<I, O, ID, F>, S, BlockedOrMore>
+    ) -> ActivatedInput<'p, CuttingFold<I, O, ID, F>, S, BlockedOrMore>
     where
         O: Send + Sync,
         ID: Fn() -> O + Sync,
         F: Fn(O, I) -> O + Sync,
     {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
         let (input, policy, sizes) = self.input_policy_sizes();
         let folder = CuttingFold {
             identity_op: identity,
@@ -131,17 +411,24 @@ pub trait BlockAdaptiveRunner<I: DivisibleIntoBlocks, S: Iterator<Item = usize>>
             folder,
             policy,
             sizes,
+            max_threads,
+            pool,
+            cache_block_size: None,
             power: PhantomData,
         }
     }
 
     /// Replace block sizes iterator (if any) by given one.
-    fn by_blocks<S2: Iterator<Item = usize>>(self, sizes: S2) -> ParametrizedInput<I, S2> {
+    fn by_blocks<S2: Iterator<Item = usize>>(self, sizes: S2) -> ParametrizedInput<'p, I, S2> {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
         let (input, policy, _) = self.input_policy_sizes();
         ParametrizedInput {
             input,
             policy,
             sizes,
+            max_threads,
+            pool,
         }
     }
 
@@ -152,6 +439,8 @@ pub trait BlockAdaptiveRunner<I: DivisibleIntoBlocks, S: Iterator<Item = usize>>
         RF: Fn(O, O) -> O + Sync,
         O: Send + Sync,
     {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
         let (input, policy, sizes) = self.input_policy_sizes();
         let folder = Fold {
             identity_op: || None,
@@ -173,6 +462,12 @@ pub trait BlockAdaptiveRunner<I: DivisibleIntoBlocks, S: Iterator<Item = usize>>
 
         let reduce_reference = &reduce_function;
         let folder_ref = &folder;
+        let parameters = SchedulingParameters {
+            policy,
+            max_threads,
+            cache_block_size: None,
+            pool,
+        };
 
         let length = input.base_length();
         let mut outputs = input.chunks(sizes.chain(once(length))).map(|input| {
@@ -180,7 +475,7 @@ pub trait BlockAdaptiveRunner<I: DivisibleIntoBlocks, S: Iterator<Item = usize>>
                 input,
                 folder_ref,
                 &|left, right| reduce_reference(left, right),
-                policy,
+                parameters,
             )
         });
         let first_output = outputs.next().unwrap();
@@ -193,16 +488,24 @@ pub trait BlockAdaptiveRunner<I: DivisibleIntoBlocks, S: Iterator<Item = usize>>
 /********************************************************************************/
 
 // Runner
-impl<I: Divisible, S: Iterator<Item = usize>> AdaptiveRunner<I, S> for ParametrizedInput<I, S> {
+impl<'p, I: Divisible, S: Iterator<Item = usize>> AdaptiveRunner<'p, I, S>
+    for ParametrizedInput<'p, I, S>
+{
     fn input_length(&self) -> usize {
         self.input.base_length()
     }
     fn input_policy_sizes(self) -> (I, Policy, S) {
         (self.input, self.policy, self.sizes)
     }
+    fn max_threads(&self) -> Option<usize> {
+        self.max_threads
+    }
+    fn pool(&self) -> Option<&'p ThreadPool> {
+        self.pool
+    }
 }
 
-impl<I: Divisible> AdaptiveRunner<I, Empty<usize>> for I {
+impl<'p, I: Divisible> AdaptiveRunner<'p, I, Empty<usize>> for I {
     fn input_length(&self) -> usize {
         self.base_length()
     }
@@ -212,14 +515,16 @@ impl<I: Divisible> AdaptiveRunner<I, Empty<usize>> for I {
 }
 
 // All
-impl<I: Divisible<Power = BasicPower>, R: AdaptiveRunner<I, Empty<usize>>>
-    AllAdaptiveRunner<I, Empty<usize>, BasicPower> for R
+impl<'p, I: Divisible<Power = BasicPower>, R: AdaptiveRunner<'p, I, Empty<usize>>>
+    AllAdaptiveRunner<'p, I, Empty<usize>, BasicPower> for R
 {
     /// Easy api when we return no results.
     fn partial_for_each<WF>(self, work_function: WF)
     where
         WF: Fn(I, usize) -> I + Sync,
     {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
         let (input, policy, _) = self.input_policy_sizes();
         let folder = WorkFold {
             work_function,
@@ -227,18 +532,26 @@ impl<I: Divisible<Power = BasicPower>, R: AdaptiveRunner<I, Empty<usize>>>
         }
         .map(|_| ());
         let reduce = |_, _| ();
-        schedule(input, &folder, &reduce, policy)
+        let parameters = SchedulingParameters {
+            policy,
+            max_threads,
+            cache_block_size: None,
+            pool,
+        };
+        schedule(input, &folder, &reduce, parameters)
     }
 }
 
-impl<I: DivisibleIntoBlocks, S: Iterator<Item = usize>, R: AdaptiveRunner<I, S>>
-    AllAdaptiveRunner<I, S, BlockedOrMore> for R
+impl<'p, I: DivisibleIntoBlocks, S: Iterator<Item = usize>, R: AdaptiveRunner<'p, I, S>>
+    AllAdaptiveRunner<'p, I, S, BlockedOrMore> for R
 {
     /// Easy api when we return no results.
     fn partial_for_each<WF>(self, work_function: WF)
     where
         WF: Fn(I, usize) -> I + Sync,
     {
+        let max_threads = self.max_threads();
+        let pool = self.pool();
         let (input, policy, sizes) = self.input_policy_sizes();
         let folder = WorkFold {
             work_function,
@@ -246,18 +559,135 @@ impl<I: DivisibleIntoBlocks, S: Iterator<Item = usize>, R: AdaptiveRunner<I, S>>
         }
         .map(|_| ());
         let reduce = |_, _| ();
+        let parameters = SchedulingParameters {
+            policy,
+            max_threads,
+            cache_block_size: None,
+            pool,
+        };
 
         for input in input.chunks(sizes) {
-            schedule(input, &folder, &reduce, policy)
+            schedule(input, &folder, &reduce, parameters)
         }
     }
 }
 
-impl<I, S> BlockAdaptiveRunner<I, S> for ParametrizedInput<I, S>
+impl<'p, I, S> BlockAdaptiveRunner<'p, I, S> for ParametrizedInput<'p, I, S>
 where
     I: DivisibleIntoBlocks,
     S: Iterator<Item = usize>,
 {
 }
 
-impl<I> BlockAdaptiveRunner<I, Empty<usize>> for I where I: DivisibleIntoBlocks {}
+impl<'p, I> BlockAdaptiveRunner<'p, I, Empty<usize>> for I where I: DivisibleIntoBlocks {}
+
+#[cfg(test)]
+mod tests {
+    use super::Policy;
+    use crate::prelude::*;
+    use crate::Folder;
+    use std::iter::repeat;
+    use std::ops::Range;
+
+    #[test]
+    fn policies_round_trip() {
+        let policies = vec![
+            Policy::DefaultPolicy,
+            Policy::Sequential,
+            Policy::Join(1000),
+            Policy::JoinContext(1000),
+            Policy::DepJoin(1000),
+            Policy::Adaptive(100, 10_000),
+            Policy::Rayon,
+            Policy::Numa(100, 10_000),
+            Policy::Tuned("sort"),
+        ];
+        for policy in policies {
+            let parsed: Policy = policy.to_string().parse().expect("invalid policy");
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", policy));
+        }
+        assert_eq!(
+            format!("{:?}", " sequential ".parse::<Policy>()),
+            "Ok(Sequential)"
+        );
+        for invalid in &[
+            "",
+            "join",
+            "join:",
+            "adaptive:100",
+            "rayon:1",
+            "seq:1",
+            "numa:a:b",
+        ] {
+            assert!(invalid.parse::<Policy>().is_err(), "{} got parsed", invalid);
+        }
+    }
+
+    /// Collect elements, telling where each part stopped.
+    struct CollectUntil;
+
+    impl Folder for CollectUntil {
+        type Input = Range<usize>;
+        type IntermediateOutput = Vec<usize>;
+        type Output = (Vec<usize>, usize);
+        fn identity(&self) -> Self::IntermediateOutput {
+            Vec::new()
+        }
+        fn fold(
+            &self,
+            mut v: Vec<usize>,
+            r: Range<usize>,
+            limit: usize,
+        ) -> (Vec<usize>, Range<usize>) {
+            let (todo, remaining) = r.divide_at(limit);
+            v.extend(todo);
+            (v, remaining)
+        }
+        fn to_output(&self, v: Vec<usize>, remaining: Range<usize>) -> Self::Output {
+            (v, remaining.start)
+        }
+    }
+
+    #[test]
+    fn user_defined_folders() {
+        let expected: Vec<usize> = (0..100_000).collect();
+        let check_end = |(v, end): (Vec<usize>, usize)| {
+            assert_eq!(v.last().map_or(end, |last| last + 1), end);
+            v
+        };
+        for policy in &[
+            Policy::DefaultPolicy,
+            Policy::Join(100),
+            Policy::Adaptive(10, 100),
+        ] {
+            let (v, _) = (0..100_000)
+                .with_policy(*policy)
+                .with_folder(CollectUntil)
+                .reduce(|(mut v1, _), (v2, end2)| {
+                    v1.extend(v2);
+                    (v1, end2)
+                });
+            assert_eq!(v, expected);
+            let v: Vec<usize> = (0..100_000)
+                .with_policy(*policy)
+                .by_blocks(repeat(30_000))
+                .with_folder(CollectUntil)
+                .into_iter()
+                .flat_map(check_end)
+                .collect();
+            assert_eq!(v, expected);
+            let v = (0..100_000)
+                .with_policy(*policy)
+                .with_folder(CollectUntil)
+                .helping_partial_fold(
+                    Vec::new(),
+                    |v, r, limit| CollectUntil.fold(v, r, limit),
+                    |mut v, o| {
+                        v.extend(check_end(o));
+                        v
+                    },
+                );
+            assert_eq!(v, expected);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Running computations on a given thread pool instead of the current one.
+//! Inputs attached to a pool with `on_pool` move the whole computation into it
+//! (with `ThreadPool::install`) as soon as scheduling starts.
+//! Block sizes then get computed from its number of threads.
+use crate::activated_input::ActivatedInput;
+use crate::cancellation::CurrentToken;
+use crate::Folder;
+use rayon_core::ThreadPool;
+
+/// Run `op` on given pool (or on the current one if none).
+pub(crate) fn run_on<R: Send, OP: FnOnce() -> R + Send>(pool: Option<&ThreadPool>, op: OP) -> R {
+    match pool {
+        Some(pool) if pool.current_thread_index().is_none() => {
+            // we wait without our token, the pool's thread takes it
+            let token = CurrentToken::get();
+            let _none = CurrentToken::none().enter();
+            pool.install(move || {
+                let _token = token.enter();
+                op()
+            })
+        }
+        _ => op(),
+    }
+}
+
+impl<'p, F: Folder, S, P> ActivatedInput<'p, F, S, P> {
+    /// Run on given thread pool instead of the current one.
+    /// With the `scoped-threads` feature, only the number of threads of the pool is used.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon_adaptive::prelude::*;
+    /// let pool = rayon::ThreadPoolBuilder::new()
+    ///     .num_threads(2)
+    ///     .build()
+    ///     .expect("failed building pool");
+    /// let (sum, in_pool) = (0..100_000)
+    ///     .into_adapt_iter()
+    ///     .fold(|| (0, true), |(s, in_pool), e| {
+    ///         (s + e, in_pool && pool.current_thread_index().is_some())
+    ///     })
+    ///     .on_pool(&pool)
+    ///     .reduce(|(s1, p1), (s2, p2)| (s1 + s2, p1 && p2));
+    /// assert_eq!(sum, 4_999_950_000);
+    /// # // the scoped threads backend runs tasks on its own threads
+    /// # #[cfg(not(feature = "scoped-threads"))]
+    /// assert!(in_pool);
+    /// ```
+    pub fn on_pool(self, pool: &'p ThreadPool) -> Self {
+        ActivatedInput {
+            pool: Some(pool),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend;
+    use crate::prelude::*;
+    use crate::Policy;
+
+    #[test]
+    fn on_pool_runs_everything_in_the_pool() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(3)
+            .build()
+            .expect("pool build failed");
+        // scoped threads workers are as many as the pool's threads, started from one of them
+        let in_pool = || {
+            assert!(
+                pool.current_thread_index().is_some()
+                    || cfg!(feature = "scoped-threads")
+                        && backend::current_thread_index().is_some()
+                        && backend::current_num_threads() == 3
+            )
+        };
+        for policy in &[Policy::DefaultPolicy, Policy::Join(100), Policy::Rayon] {
+            let sum = (0..10_000)
+                .into_adapt_iter()
+                .with_policy(*policy)
+                .on_pool(&pool)
+                .fold(
+                    || 0,
+                    |s, e| {
+                        in_pool();
+                        s + e
+                    },
+                )
+                .reduce(|a, b| a + b);
+            assert_eq!(sum, 49_995_000);
+            let v: Vec<usize> = (0..10_000)
+                .into_adapt_iter()
+                .map(|e| {
+                    in_pool();
+                    e
+                })
+                .with_policy(*policy)
+                .on_pool(&pool)
+                .collect();
+            assert_eq!(v, (0..10_000).collect::<Vec<usize>>());
+            let sum = (0..10_000)
+                .into_adapt_iter()
+                .fold(
+                    || 0,
+                    |s, e| {
+                        in_pool();
+                        s + e
+                    },
+                )
+                .on_pool(&pool)
+                .helping_partial_fold(
+                    0,
+                    |s, r, limit| {
+                        in_pool();
+                        let (todo, remaining) = r.divide_at(limit);
+                        (s + todo.into_iter().sum::<usize>(), remaining)
+                    },
+                    |a, b| a + b,
+                );
+            assert_eq!(sum, 49_995_000);
+            // parametrized inputs run there too
+            let sum: usize = (0..10_000)
+                .into_adapt_iter()
+                .map(|e| {
+                    in_pool();
+                    2 * e
+                })
+                .filter(|&e| e % 4 == 0)
+                .with_policy(*policy)
+                .on_pool(&pool)
+                .sum();
+            assert_eq!(sum, 49_990_000);
+        }
+    }
+}
@@ -1,25 +1,31 @@
 //! Let factorize a huge amount of scheduling policies into one api.
-use crate::atomiclist::{AtomicLink, AtomicList};
+use crate::atomiclist::{AtomicLink, AtomicList, Completion};
+use crate::backend::{self, Scope};
+use crate::cancellation::{check_cancellation, CurrentToken};
 use crate::depjoin;
-use crate::folders::Folder;
+use crate::environment::environment_policy;
+use crate::folders::{record, Folder, SchedulingEvent};
+use crate::nesting::{sequential_depth, DepthGuard};
+use crate::numa::{is_remote, steal_request, NOT_STOLEN, REFUSED};
+use crate::outputs::{concatenate, gathering, Outputs};
+use crate::pool::run_on;
 use crate::prelude::*;
 use crate::smallchannel::{small_channel, SmallSender};
+use crate::threads::ThreadsLimit;
 use crate::traits::Divisible;
+use crate::tune::tuned_policy;
 use crate::utils::powers;
 use crate::Policy;
-use rayon::{current_num_threads, Scope};
+use rayon_core::ThreadPool;
 #[cfg(feature = "logs")]
 use rayon_logs::subgraph;
-use std::cell::RefCell;
-use std::cmp::min;
-use std::iter::once;
+use std::cmp::{max, min};
 use std::iter::repeat;
+use std::iter::{from_fn, once};
 use std::marker::PhantomData;
-use std::sync::atomic::{AtomicBool, Ordering};
-
-// we use this boolean to prevent fine grain parallelism when coarse grain
-// parallelism is still available in composed algorithms.
-thread_local!(static SEQUENCE: RefCell<bool> = RefCell::new(false));
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(any(feature = "stats", feature = "trace"))]
+use std::time::Instant;
 
 /// by default, min block size is log(n)
 fn default_min_block_size(n: usize) -> usize {
@@ -32,86 +38,213 @@ fn default_max_block_size(n: usize) -> usize {
     ((n as f64).sqrt() * 10.0f64).ceil() as usize
 }
 
+/// default max block size, capped so that a block still fits in a core's cache
+/// (when we know the size of the elements).
+/// we never go below the default min block size.
+fn capped_max_block_size(cache_block_size: Option<usize>) -> impl Fn(usize) -> usize + Send + Copy {
+    move |n| match cache_block_size {
+        Some(cache_block_size) => max(
+            min(default_max_block_size(n), cache_block_size),
+            default_min_block_size(n),
+        ),
+        None => default_max_block_size(n),
+    }
+}
+
 /// compute a block size with the given function.
 /// this allows us to ensure we enforce important bounds on sizes.
-fn compute_size<F: Fn(usize) -> usize>(n: usize, sizing_function: F) -> usize {
-    let p = current_num_threads();
-    std::cmp::max(min(n / (2 * p), sizing_function(n)), 1)
+fn compute_size<F: Fn(usize) -> usize>(n: usize, threads: usize, sizing_function: F) -> usize {
+    std::cmp::max(min(n / (2 * threads), sizing_function(n)), 1)
+}
+
+/// Start executing a task, possibly stolen from another thread.
+fn start_task<F: Folder>(folder: &F, stolen: bool) -> DepthGuard {
+    check_cancellation();
+    if stolen {
+        record(folder, SchedulingEvent::Steal)
+    }
+    DepthGuard::task(stolen)
+}
+
+/// Block until given operation completes, telling the folder how long we waited.
+fn wait_for<F: Folder, R, W: FnOnce() -> R>(folder: &F, waiting: W) -> R {
+    #[cfg(any(feature = "stats", feature = "trace"))]
+    {
+        let start = Instant::now();
+        let result = waiting();
+        record(folder, SchedulingEvent::Wait(start.elapsed()));
+        result
+    }
+    #[cfg(not(any(feature = "stats", feature = "trace")))]
+    {
+        let _ = folder;
+        waiting()
+    }
+}
+
+/// Load tuned policies and apply environment overrides to the default one.
+fn effective_policy(policy: Policy) -> Policy {
+    let policy = match policy {
+        Policy::DefaultPolicy => environment_policy(None).unwrap_or(policy),
+        _ => policy,
+    };
+    match policy {
+        Policy::Tuned(name) => tuned_policy(name),
+        _ => policy,
+    }
+}
+
+/// How to schedule a computation, besides its input and folder.
+#[derive(Clone, Copy)]
+pub(crate) struct SchedulingParameters<'p> {
+    pub(crate) policy: Policy,
+    /// How many threads can work on it (the whole pool if none).
+    pub(crate) max_threads: Option<usize>,
+    /// Largest block fitting in a core's cache (if we know the elements).
+    pub(crate) cache_block_size: Option<usize>,
+    /// Where it runs (the current pool if none).
+    pub(crate) pool: Option<&'p ThreadPool>,
 }
 
 pub(crate) fn schedule<F, RF>(
+    input: F::Input,
+    folder: &F,
+    reduce_function: &RF,
+    parameters: SchedulingParameters,
+) -> F::Output
+where
+    F: Folder,
+    RF: Fn(F::Output, F::Output) -> F::Output + Sync,
+{
+    run_on(parameters.pool, move || {
+        // nested computations share our places
+        let limit = &ThreadsLimit::new(parameters.max_threads);
+        schedule_on_current_pool(
+            input,
+            folder,
+            reduce_function,
+            parameters.policy,
+            limit,
+            parameters.cache_block_size,
+        )
+    })
+}
+
+fn schedule_on_current_pool<F, RF>(
     input: F::Input,
     folder: &F,
     reduce_function: &RF,
     policy: Policy,
+    limit: &ThreadsLimit,
+    cache_block_size: Option<usize>,
 ) -> F::Output
 where
     F: Folder,
     RF: Fn(F::Output, F::Output) -> F::Output + Sync,
 {
-    SEQUENCE.with(|s| {
-        if *s.borrow() || input.base_length() == 1 {
-            schedule_sequential(input, folder)
-        } else {
-            let block_size = match policy {
-                Policy::Sequential => input.base_length(),
-                Policy::DefaultPolicy => compute_size(input.base_length(), default_min_block_size),
-                Policy::Join(block_size)
-                | Policy::JoinContext(block_size)
-                | Policy::DepJoin(block_size)
-                | Policy::Adaptive(block_size, _) => block_size,
-                Policy::Rayon => 1,
-            };
-            match policy {
-                Policy::Sequential => schedule_sequential(input, folder),
-                Policy::Join(_) => schedule_join(input, folder, reduce_function, block_size),
-                Policy::JoinContext(_) => {
-                    schedule_join_context(input, folder, reduce_function, block_size)
-                }
-                Policy::DepJoin(_) => schedule_depjoin(input, folder, reduce_function, block_size),
-                Policy::Adaptive(min, max) => schedule_adaptive(
+    if input.base_length() == 1 {
+        return schedule_sequential(input, folder);
+    }
+    let policy = effective_policy(policy);
+    let threads = limit.max_threads();
+    if sequential_depth() > 0 {
+        if let Policy::DefaultPolicy = policy {
+            // an enclosing computation still has coarse grain parallelism available.
+            // unless told otherwise, only let idle threads steal from us.
+            // explicit policies are always honoured.
+            return schedule_adaptive(
+                input,
+                folder.identity(),
+                folder,
+                reduce_function,
+                AdaptiveParameters {
+                    block_sizes: (
+                        default_min_block_size,
+                        capped_max_block_size(cache_block_size),
+                    ),
+                    numa_aware: false,
+                    limit,
+                },
+            );
+        }
+    }
+    let block_size = match policy {
+        Policy::Sequential => input.base_length(),
+        Policy::DefaultPolicy => compute_size(input.base_length(), threads, default_min_block_size),
+        Policy::Join(block_size)
+        | Policy::JoinContext(block_size)
+        | Policy::DepJoin(block_size)
+        | Policy::Adaptive(block_size, _)
+        | Policy::Numa(block_size, _) => block_size,
+        Policy::Rayon => 1,
+        Policy::Tuned(_) => unreachable!("tuned policies are loaded beforehand"),
+    };
+    match policy {
+        Policy::Sequential => schedule_sequential(input, folder),
+        Policy::Join(_) => schedule_join(input, folder, reduce_function, block_size),
+        Policy::JoinContext(_) => schedule_join_context(input, folder, reduce_function, block_size),
+        Policy::DepJoin(_) => schedule_depjoin(input, folder, reduce_function, block_size),
+        Policy::Adaptive(min, max) => schedule_adaptive(
+            input,
+            folder.identity(),
+            folder,
+            reduce_function,
+            AdaptiveParameters {
+                block_sizes: (|_| min, |_| max),
+                numa_aware: false,
+                limit,
+            },
+        ),
+        Policy::Numa(min, max) => schedule_adaptive(
+            input,
+            folder.identity(),
+            folder,
+            reduce_function,
+            AdaptiveParameters {
+                block_sizes: (|_| min, |_| max),
+                numa_aware: true,
+                limit,
+            },
+        ),
+        Policy::DefaultPolicy => {
+            if block_size * 2 * threads >= input.base_length() //TODO ASK should I call schedule_adaptive in this case?
+        || (threads as f64).log2() * (50.0f64)
+            >= (input.base_length() as f64) / (block_size as f64)
+            {
+                let max_size = compute_size(
+                    input.base_length(),
+                    threads,
+                    capped_max_block_size(cache_block_size),
+                );
+                schedule_join_context_max_size(input, folder, reduce_function, block_size, max_size)
+            } else {
+                let max_size = compute_size(
+                    input.base_length(),
+                    threads,
+                    capped_max_block_size(cache_block_size),
+                );
+                schedule_adaptive(
                     input,
                     folder.identity(),
                     folder,
                     reduce_function,
-                    (|_| min, |_| max),
-                ),
-                Policy::DefaultPolicy => {
-                    if block_size * 2 * current_num_threads() >= input.base_length() //TODO ASK should I call schedule_adaptive in this case?
-                || (current_num_threads() as f64).log2() * (50.0f64)
-                    >= (input.base_length() as f64) / (block_size as f64)
-                    {
-                        let max_size = compute_size(input.base_length(), default_max_block_size);
-                        schedule_join_context_max_size(
-                            input,
-                            folder,
-                            reduce_function,
-                            block_size,
-                            max_size,
-                        )
-                    } else {
-                        let max_size = compute_size(input.base_length(), default_max_block_size);
-                        schedule_adaptive(
-                            input,
-                            folder.identity(),
-                            folder,
-                            reduce_function,
-                            (|_| block_size, |_| max_size),
-                        )
-                    }
-                }
-                Policy::Rayon => schedule_rayon_join_context(
-                    input,
-                    folder,
-                    reduce_function,
-                    rayon::current_num_threads(),
-                ),
+                    AdaptiveParameters {
+                        block_sizes: (|_| block_size, |_| max_size),
+                        numa_aware: false,
+                        limit,
+                    },
+                )
             }
         }
-    })
+        Policy::Rayon => {
+            schedule_rayon_join_context(input, folder, reduce_function, threads, threads)
+        }
+        Policy::Tuned(_) => unreachable!("tuned policies are loaded beforehand"),
+    }
 }
 
 fn schedule_sequential<F: Folder>(input: F::Input, folder: &F) -> F::Output {
+    check_cancellation();
     let len = input.base_length();
     let (io, i) = folder.fold(folder.identity(), input, len);
     folder.to_output(io, i)
@@ -132,10 +265,15 @@ where
         schedule_sequential(input, folder)
     } else {
         let (i1, i2) = input.divide();
-        let (r1, r2) = rayon::join(
-            || schedule_join(i1, folder, reduce_function, block_size),
-            || schedule_join(i2, folder, reduce_function, block_size),
+        record(folder, SchedulingEvent::Split);
+        let (r1, r2) = backend::join_context(
+            |_| schedule_join(i1, folder, reduce_function, block_size),
+            |c| {
+                let _depth = start_task(folder, c.migrated());
+                schedule_join(i2, folder, reduce_function, block_size)
+            },
         );
+        record(folder, SchedulingEvent::Reduction);
         reduce_function(r1, r2)
     }
 }
@@ -155,9 +293,11 @@ where
         schedule_sequential(input, folder)
     } else {
         let (i1, i2) = input.divide();
-        let (r1, r2) = rayon::join_context(
+        record(folder, SchedulingEvent::Split);
+        let (r1, r2) = backend::join_context(
             |_| schedule_join_context(i1, folder, reduce_function, block_size),
             |c| {
+                let _depth = start_task(folder, c.migrated());
                 if c.migrated() {
                     schedule_join_context(i2, folder, reduce_function, block_size)
                 } else {
@@ -165,6 +305,7 @@ where
                 }
             },
         );
+        record(folder, SchedulingEvent::Reduction);
         reduce_function(r1, r2)
     }
 }
@@ -174,6 +315,7 @@ fn schedule_rayon_join_context<F, RF>(
     folder: &F,
     reduce_function: &RF,
     split_limit: usize,
+    threads: usize,
 ) -> F::Output
 where
     F: Folder,
@@ -183,21 +325,25 @@ where
         schedule_sequential(input, folder)
     } else {
         let (i1, i2) = input.divide();
-        let (r1, r2) = rayon::join_context(
-            |_| schedule_rayon_join_context(i1, folder, reduce_function, split_limit / 2),
+        record(folder, SchedulingEvent::Split);
+        let (r1, r2) = backend::join_context(
+            |_| schedule_rayon_join_context(i1, folder, reduce_function, split_limit / 2, threads),
             |c| {
+                let _depth = start_task(folder, c.migrated());
                 if c.migrated() {
+                    schedule_rayon_join_context(i2, folder, reduce_function, threads * 2, threads)
+                } else {
                     schedule_rayon_join_context(
                         i2,
                         folder,
                         reduce_function,
-                        rayon::current_num_threads() * 2,
+                        split_limit / 2,
+                        threads,
                     )
-                } else {
-                    schedule_rayon_join_context(i2, folder, reduce_function, split_limit / 2)
                 }
             },
         );
+        record(folder, SchedulingEvent::Reduction);
         reduce_function(r1, r2)
     }
 }
@@ -218,19 +364,21 @@ where
         schedule_sequential(input, folder)
     } else {
         let (i1, i2) = input.divide();
-        let (r1, r2) = rayon::join_context(
+        record(folder, SchedulingEvent::Split);
+        let (r1, r2) = backend::join_context(
             |_| schedule_join_context_max_size(i1, folder, reduce_function, min_size, max_size),
             |c| {
+                let _depth = start_task(folder, c.migrated());
                 if len > max_size || c.migrated() {
                     schedule_join_context_max_size(i2, folder, reduce_function, min_size, max_size)
                 } else {
-                    SEQUENCE.with(|s| *s.borrow_mut() = true); // we force subtasks to work sequentially
-                    let sequential_output = schedule_sequential(i2, folder);
-                    SEQUENCE.with(|s| *s.borrow_mut() = false);
-                    sequential_output
+                    // we force nested computations to only use idle threads
+                    let _sequential = DepthGuard::sequential_block();
+                    schedule_sequential(i2, folder)
                 }
             },
         );
+        record(folder, SchedulingEvent::Reduction);
         reduce_function(r1, r2)
     }
 }
@@ -250,14 +398,36 @@ where
         schedule_sequential(input, folder)
     } else {
         let (i1, i2) = input.divide();
+        record(folder, SchedulingEvent::Split);
         depjoin(
             || schedule_depjoin(i1, folder, reduce_function, block_size),
             || schedule_depjoin(i2, folder, reduce_function, block_size),
-            reduce_function,
+            |r1, r2| {
+                record(folder, SchedulingEvent::Reduction);
+                reduce_function(r1, r2)
+            },
         )
     }
 }
 
+/// How an adaptive worker stopped.
+enum WorkerEnd<F: Folder> {
+    /// All its input got folded.
+    Done(F::Output),
+    /// Its thief found no place : what is left goes on in a new join.
+    Refused(F::IntermediateOutput, F::Input),
+}
+
+/// How adaptive workers size their blocks and who can steal from them.
+#[derive(Clone, Copy)]
+struct AdaptiveParameters<'l, MINSIZE, MAXSIZE> {
+    /// Min and max block sizes, computed from the input's length.
+    block_sizes: (MINSIZE, MAXSIZE),
+    /// Do thieves from other NUMA nodes only get a quarter of the input.
+    numa_aware: bool,
+    limit: &'l ThreadsLimit,
+}
+
 struct AdaptiveWorker<
     'a,
     'b,
@@ -268,14 +438,14 @@ struct AdaptiveWorker<
 > {
     input: F::Input,
     partial_output: F::IntermediateOutput,
-    block_sizes: (MINSIZE, MAXSIZE),
+    parameters: AdaptiveParameters<'b, MINSIZE, MAXSIZE>,
     min_block_size: usize,
     max_block_size: usize,
-    stolen: &'a AtomicBool,
+    stolen: &'a AtomicUsize,
     sender: SmallSender<F::Input>,
     folder: &'b F,
     reduce_function: &'b RF,
-    phantom: PhantomData<(F::Output)>,
+    phantom: PhantomData<F::Output>,
 }
 
 impl<'a, 'b, F, RF, MINSIZE, MAXSIZE> AdaptiveWorker<'a, 'b, F, RF, MINSIZE, MAXSIZE>
@@ -288,19 +458,21 @@ where
     fn new(
         input: F::Input,
         partial_output: F::IntermediateOutput,
-        block_sizes: (MINSIZE, MAXSIZE),
-        stolen: &'a AtomicBool,
+        parameters: AdaptiveParameters<'b, MINSIZE, MAXSIZE>,
+        stolen: &'a AtomicUsize,
         sender: SmallSender<F::Input>,
         folder: &'b F,
         reduce_function: &'b RF,
     ) -> Self {
-        let min_block_size = compute_size(input.base_length(), block_sizes.0);
-        let max_block_size = compute_size(input.base_length(), block_sizes.1);
+        let threads = parameters.limit.max_threads();
+        let (min_sizes, max_sizes) = parameters.block_sizes;
+        let min_block_size = compute_size(input.base_length(), threads, min_sizes);
+        let max_block_size = compute_size(input.base_length(), threads, max_sizes);
 
         AdaptiveWorker {
             input,
             partial_output,
-            block_sizes,
+            parameters,
             min_block_size,
             max_block_size,
             stolen,
@@ -327,24 +499,25 @@ where
     //        )
     //    }
 
-    fn schedule(self) -> F::Output {
+    fn schedule(self) -> WorkerEnd<F> {
         // TODO: automate this min everywhere ?
         // TODO: factorize a little bit
         // start by computing a little bit in order to get a first output
         let partial_output = self.partial_output;
         let remaining_input = self.input;
-        let stolen_bool = self.stolen;
+        let stolen = self.stolen;
         let folder = self.folder;
         let max_size = self.max_block_size;
         match powers(self.min_block_size)
             .take_while(|&size| size < max_size)
             .chain(repeat(max_size))
-            .take_while(|_| !stolen_bool.load(Ordering::Relaxed))
+            .take_while(|_| stolen.load(Ordering::Relaxed) == NOT_STOLEN)
             .try_fold(
                 (partial_output, remaining_input),
                 |(output, input), size| {
                     let checked_size = min(input.base_length(), size); //TODO: remove all these mins
                     if checked_size > 0 {
+                        check_cancellation();
                         Ok(folder.fold(output, input, checked_size))
                     } else {
                         Err(folder.to_output(output, input))
@@ -353,38 +526,97 @@ where
             ) {
             Ok((mut output, mut remaining_input)) => {
                 let remaining_length = remaining_input.base_length();
-                if remaining_length > self.min_block_size {
+                if stolen.load(Ordering::Relaxed) == REFUSED
+                    && remaining_length > self.min_block_size
+                {
+                    // the thief found no place : one more block and others can try again
+                    let (output, remaining_input) =
+                        folder.fold(output, remaining_input, self.min_block_size);
+                    WorkerEnd::Refused(output, remaining_input)
+                } else if self.parameters.numa_aware
+                    && remaining_length > self.min_block_size
+                    && is_remote(stolen.load(Ordering::Relaxed))
+                {
+                    // the thief is far from the data : he only gets a quarter
+                    let (my_part, kept_part, his_part) = remote_split(remaining_input);
+                    record(folder, SchedulingEvent::Split);
+                    record(folder, SchedulingEvent::Split);
+                    if his_part.base_length() > 0 {
+                        record(
+                            folder,
+                            SchedulingEvent::StealAnswer {
+                                given: his_part.base_length(),
+                                kept: my_part.base_length() + kept_part.base_length(),
+                                remote: true,
+                            },
+                        );
+                        self.sender.send(his_part);
+                    }
+                    let my_output = schedule_adaptive(
+                        my_part,
+                        output,
+                        self.folder,
+                        self.reduce_function,
+                        self.parameters,
+                    );
+                    let kept_output = schedule_adaptive(
+                        kept_part,
+                        folder.identity(),
+                        self.folder,
+                        self.reduce_function,
+                        self.parameters,
+                    );
+                    record(folder, SchedulingEvent::Reduction);
+                    WorkerEnd::Done((self.reduce_function)(my_output, kept_output))
+                } else if remaining_length > self.min_block_size {
                     let (my_half, his_half) = remaining_input.divide();
+                    record(folder, SchedulingEvent::Split);
                     if his_half.base_length() > 0 {
+                        record(
+                            folder,
+                            SchedulingEvent::StealAnswer {
+                                given: his_half.base_length(),
+                                kept: my_half.base_length(),
+                                remote: is_remote(stolen.load(Ordering::Relaxed)),
+                            },
+                        );
                         self.sender.send(his_half);
                     }
-                    schedule_adaptive(
+                    WorkerEnd::Done(schedule_adaptive(
                         my_half,
                         output,
                         self.folder,
                         self.reduce_function,
-                        self.block_sizes,
-                    )
+                        self.parameters,
+                    ))
                 } else {
                     if remaining_length != 0 {
                         let final_result = folder.fold(output, remaining_input, remaining_length);
                         output = final_result.0;
                         remaining_input = final_result.1;
                     }
-                    self.folder.to_output(output, remaining_input)
+                    WorkerEnd::Done(self.folder.to_output(output, remaining_input))
                 }
             }
-            Err(output) => output,
+            Err(output) => WorkerEnd::Done(output),
         }
     }
 }
 
+/// Cut input for a thief from another NUMA node.
+/// We keep the first three quarters (in two parts) and he gets the last one.
+fn remote_split<I: Divisible>(input: I) -> (I, I, I) {
+    let (my_part, end) = input.divide();
+    let (kept_part, his_part) = end.divide();
+    (my_part, kept_part, his_part)
+}
+
 fn schedule_adaptive<F, RF, MINSIZE, MAXSIZE>(
     input: F::Input,
     partial_output: F::IntermediateOutput,
     folder: &F,
     reduce_function: &RF,
-    block_sizes: (MINSIZE, MAXSIZE),
+    parameters: AdaptiveParameters<MINSIZE, MAXSIZE>,
 ) -> F::Output
 where
     F: Folder,
@@ -392,18 +624,23 @@ where
     MINSIZE: Fn(usize) -> usize + Send + Copy,
     MAXSIZE: Fn(usize) -> usize + Send + Copy,
 {
-    let size = input.base_length();
-    if size <= compute_size(size, block_sizes.0) {
-        let (io, i) = folder.fold(partial_output, input, size);
-        folder.to_output(io, i)
-    } else {
-        let stolen = &AtomicBool::new(false);
+    let limit = parameters.limit;
+    let (mut input, mut partial_output) = (input, partial_output);
+    // we loop (instead of recursing) each time a thief finds no place
+    loop {
+        let size = input.base_length();
+        if size <= compute_size(size, limit.max_threads(), parameters.block_sizes.0) {
+            check_cancellation();
+            let (io, i) = folder.fold(partial_output, input, size);
+            return folder.to_output(io, i);
+        }
+        let stolen = &AtomicUsize::new(NOT_STOLEN);
         let (sender, receiver) = small_channel();
 
         let worker = AdaptiveWorker::new(
             input,
             partial_output,
-            block_sizes,
+            parameters,
             stolen,
             sender,
             folder,
@@ -411,36 +648,57 @@ where
         );
 
         //TODO depjoin instead of join
-        let (o1, maybe_o2) = rayon::join(
-            move || worker.schedule(),
-            move || {
-                stolen.store(true, Ordering::Relaxed);
+        let (end, maybe_o2) = backend::join_context(
+            move |_| worker.schedule(),
+            move |c| {
+                let _place = if c.migrated() {
+                    let place = limit.enter();
+                    if place.is_none() {
+                        // we leave the worker alone and go on with other tasks
+                        stolen.store(REFUSED, Ordering::Relaxed);
+                        return None;
+                    }
+                    place
+                } else {
+                    None
+                };
+                let _depth = start_task(folder, c.migrated());
+                record(folder, SchedulingEvent::StealRequest);
+                stolen.store(steal_request(), Ordering::Relaxed);
                 let input: F::Input;
                 #[cfg(feature = "logs")]
                 {
-                    let option = subgraph("waiting", 1, || receiver.recv());
+                    let option = subgraph("waiting", 1, || wait_for(folder, || receiver.recv()));
                     input = option?;
                 }
                 #[cfg(not(feature = "logs"))]
                 {
-                    input = receiver.recv()?;
+                    input = wait_for(folder, || receiver.recv())?;
+                }
+                if input.base_length() == 0 {
+                    // cancelled since it got sent : nothing left for us
+                    return None;
                 }
-                assert!(input.base_length() > 0);
                 Some(schedule_adaptive(
                     input,
                     folder.identity(),
                     folder,
                     reduce_function,
-                    block_sizes,
+                    parameters,
                 ))
             },
         );
 
-        let fusion_needed = maybe_o2.is_some();
-        if fusion_needed {
-            reduce_function(o1, maybe_o2.unwrap())
-        } else {
-            o1
+        match (end, maybe_o2) {
+            (WorkerEnd::Done(o1), Some(o2)) => {
+                record(folder, SchedulingEvent::Reduction);
+                return reduce_function(o1, o2);
+            }
+            (WorkerEnd::Done(o1), None) => return o1,
+            (WorkerEnd::Refused(output, remaining_input), _) => {
+                partial_output = output;
+                input = remaining_input;
+            }
         }
     }
 }
@@ -457,14 +715,42 @@ enum FoldElement<I, O2> {
     Output(O2),
 }
 
+/// How a slave cuts its remaining input when the master asks for its results.
+/// The slave folds the left part and hands back the right part to the master.
+type RetrievalCut<I> = fn(I) -> (I, I);
+
+fn cut_at_start<I: DivisibleIntoBlocks>(input: I) -> (I, I) {
+    input.divide_at(0)
+}
+
+/// What a thief hands back to the master : its output and what it did not fold.
+type Stolen<F> = (Option<<F as Folder>::Output>, Option<<F as Folder>::Input>);
+
+/// How the master folds its own blocks and retrieves the slaves' outputs.
+struct Master<'m, FOLD1, RET> {
+    fold: &'m FOLD1,
+    retrieve: &'m RET,
+}
+
+/// How slaves of an adaptive helping fold size their blocks and give back their input.
+#[derive(Clone, Copy)]
+struct HelpParameters<'l, C> {
+    min_size: usize,
+    max_size: usize,
+    retrieval_cut: C,
+    limit: &'l ThreadsLimit,
+}
+
+/// Fold sequentially with `fold1` while idle threads steal parts of the input
+/// and fold them with `slave_folder`. Their outputs are retrieved in order.
 pub(crate) fn fold_with_help<F, O1, FOLD1, RET, S>(
     input: F::Input,
     o1: O1,
     fold1: FOLD1,
-    slave_folder: &F,
+    slave_folder: F,
     retrieve: RET,
     sizes: S,
-    policy: Policy,
+    parameters: SchedulingParameters,
 ) -> O1
 where
     F: Folder + Send,
@@ -474,68 +760,292 @@ where
     RET: Fn(O1, F::Output) -> O1 + Sync,
     S: Iterator<Item = usize> + Send,
 {
+    let master = Master {
+        fold: &fold1,
+        retrieve: &retrieve,
+    };
+    run_on(parameters.pool, move || {
+        let input_length = input.base_length();
+        let chunks = input.chunks(sizes.chain(once(input_length)));
+        fold_chunks_with_help(
+            chunks,
+            input_length,
+            o1,
+            master,
+            slave_folder,
+            parameters,
+            cut_at_start,
+        )
+    })
+}
+
+/// Same as `fold_with_help` but only relying on `divide`.
+/// When asked for their results, slaves fold half of what is left
+/// and give the other half back to the master.
+pub(crate) fn fold_with_divide_help<F, O1, FOLD1, RET>(
+    input: F::Input,
+    o1: O1,
+    fold1: FOLD1,
+    slave_folder: F,
+    retrieve: RET,
+    parameters: SchedulingParameters,
+) -> O1
+where
+    F: Folder + Send,
+    O1: Send,
+    FOLD1: Fn(O1, F::Input, usize) -> (O1, F::Input) + Sync,
+    RET: Fn(O1, F::Output) -> O1 + Sync,
+{
+    let master = Master {
+        fold: &fold1,
+        retrieve: &retrieve,
+    };
+    run_on(parameters.pool, move || {
+        let input_length = input.base_length();
+        fold_chunks_with_help(
+            once(input),
+            input_length,
+            o1,
+            master,
+            slave_folder,
+            parameters,
+            Divisible::divide,
+        )
+    })
+}
+
+fn fold_chunks_with_help<F, O1, FOLD1, RET, C>(
+    chunks: C,
+    input_length: usize,
+    o1: O1,
+    master: Master<FOLD1, RET>,
+    slave_folder: F,
+    parameters: SchedulingParameters,
+    retrieval_cut: RetrievalCut<F::Input>,
+) -> O1
+where
+    F: Folder + Send,
+    O1: Send,
+    FOLD1: Fn(O1, F::Input, usize) -> (O1, F::Input) + Sync,
+    RET: Fn(O1, F::Output) -> O1 + Sync,
+    C: Iterator<Item = F::Input> + Send,
+{
+    let policy = effective_policy(parameters.policy);
+    let parameters = SchedulingParameters {
+        policy,
+        ..parameters
+    };
+    let limit = &ThreadsLimit::new(parameters.max_threads);
+    let threads = limit.max_threads();
     let (min_size, max_size) = match policy {
-        Policy::Adaptive(min_size, max_size) => (min_size, max_size),
+        Policy::Adaptive(min_size, max_size) | Policy::Numa(min_size, max_size) => {
+            (min_size, max_size)
+        }
         Policy::DefaultPolicy => (
-            compute_size(input.base_length(), default_min_block_size),
-            compute_size(input.base_length(), default_max_block_size),
+            compute_size(input_length, threads, default_min_block_size),
+            compute_size(
+                input_length,
+                threads,
+                capped_max_block_size(parameters.cache_block_size),
+            ),
         ),
-        _ => panic!("for now only adaptive or default policies for help"),
+        Policy::Sequential => {
+            // nobody helps : the master folds everything
+            return chunks.fold(o1, |o1, chunk| {
+                let length = chunk.base_length();
+                master_fold(master.fold, &slave_folder, o1, chunk, length).0
+            });
+        }
+        Policy::Join(block_size)
+        | Policy::JoinContext(block_size)
+        | Policy::DepJoin(block_size) => {
+            let list_folder = gathering(slave_folder);
+            return chunks.fold(o1, |o1, chunk| {
+                fold_with_static_help(
+                    o1,
+                    chunk,
+                    &master,
+                    &list_folder,
+                    block_size,
+                    parameters,
+                    limit,
+                )
+            });
+        }
+        Policy::Rayon => {
+            // master gets as much as each thread in a perfectly balanced split
+            let list_folder = gathering(slave_folder);
+            return chunks.fold(o1, |o1, chunk| {
+                let block_size = std::cmp::max(chunk.base_length() / threads, 1);
+                fold_with_static_help(
+                    o1,
+                    chunk,
+                    &master,
+                    &list_folder,
+                    block_size,
+                    parameters,
+                    limit,
+                )
+            });
+        }
+        Policy::Tuned(_) => unreachable!("tuned policies are loaded beforehand"),
+    };
+    let slave_folder = &slave_folder;
+    let stolen_stuffs: &AtomicList<Stolen<F>> = &AtomicList::new();
+    let help = HelpParameters {
+        min_size,
+        max_size,
+        retrieval_cut,
+        limit,
     };
-    let input_length = input.base_length();
-    let stolen_stuffs: &AtomicList<(Option<F::Output>, Option<F::Input>)> = &AtomicList::new();
-    let completed_sizes = sizes.chain(once(input_length));
-    rayon::scope(|s| {
-        input
-            .chunks(completed_sizes)
+    backend::scope(|s| {
+        chunks
             .flat_map(|chunk| {
-                once(FoldElement::Input(chunk)).chain(stolen_stuffs.iter().flat_map(|(o2, i)| {
+                let mut retrieved = stolen_stuffs.iter();
+                let retrieved = from_fn(move || wait_for(slave_folder, || retrieved.next()));
+                once(FoldElement::Input(chunk)).chain(retrieved.flat_map(|(o2, i)| {
                     o2.map(FoldElement::Output)
                         .into_iter()
-                        .chain(i.map(FoldElement::Input).into_iter())
+                        .chain(i.map(FoldElement::Input))
                 }))
             })
             .fold(o1, |o1, element| match element {
-                FoldElement::Input(i) => master_work(
-                    s,
-                    o1,
-                    i,
-                    &fold1,
-                    slave_folder,
-                    stolen_stuffs,
-                    min_size,
-                    max_size,
-                ),
-                FoldElement::Output(o2) => retrieve(o1, o2),
+                FoldElement::Input(i) => {
+                    master_work(s, o1, i, master.fold, slave_folder, stolen_stuffs, help)
+                }
+                FoldElement::Output(o2) => {
+                    record(slave_folder, SchedulingEvent::Reduction);
+                    (master.retrieve)(o1, o2)
+                }
             })
     })
 }
 
+/// Helping for join based policies.
+/// We divide statically until reaching block size. The master folds the leftmost block
+/// while all others get scheduled with the given policy by the slaves' folder.
+/// Slaves outputs are then retrieved in order.
+fn fold_with_static_help<F, O1, FOLD1, RET, T>(
+    o1: O1,
+    input: F::Input,
+    master: &Master<FOLD1, RET>,
+    list_folder: &F,
+    block_size: usize,
+    parameters: SchedulingParameters,
+    limit: &ThreadsLimit,
+) -> O1
+where
+    F: Folder<Output = Outputs<T>>,
+    O1: Send,
+    FOLD1: Fn(O1, F::Input, usize) -> (O1, F::Input) + Sync,
+    RET: Fn(O1, T) -> O1 + Sync,
+    T: Send + Sync,
+{
+    let (o1, slaves_outputs) = master_static_work(
+        o1,
+        input,
+        master.fold,
+        list_folder,
+        block_size,
+        parameters,
+        limit,
+    );
+    slaves_outputs.into_iter().fold(o1, |o1, o2| {
+        record(list_folder, SchedulingEvent::Reduction);
+        (master.retrieve)(o1, o2)
+    })
+}
+
+fn master_static_work<F, O1, FOLD1, T>(
+    o1: O1,
+    input: F::Input,
+    fold1: &FOLD1,
+    list_folder: &F,
+    block_size: usize,
+    parameters: SchedulingParameters,
+    limit: &ThreadsLimit,
+) -> (O1, Outputs<T>)
+where
+    F: Folder<Output = Outputs<T>>,
+    O1: Send,
+    FOLD1: Fn(O1, F::Input, usize) -> (O1, F::Input) + Sync,
+    T: Send + Sync,
+{
+    let length = input.base_length();
+    if length <= block_size {
+        (
+            master_fold(fold1, list_folder, o1, input, length).0,
+            Outputs::new(),
+        )
+    } else {
+        let (my_half, his_half) = input.divide();
+        record(list_folder, SchedulingEvent::Split);
+        let ((o1, outputs), his_outputs) = backend::join_context(
+            |_| {
+                master_static_work(
+                    o1,
+                    my_half,
+                    fold1,
+                    list_folder,
+                    block_size,
+                    parameters,
+                    limit,
+                )
+            },
+            |c| {
+                let _depth = start_task(list_folder, c.migrated());
+                schedule_on_current_pool(
+                    his_half,
+                    list_folder,
+                    &concatenate,
+                    parameters.policy,
+                    limit,
+                    parameters.cache_block_size,
+                )
+            },
+        );
+        record(list_folder, SchedulingEvent::Reduction);
+        let outputs = concatenate(outputs, his_outputs);
+        (o1, outputs)
+    }
+}
+
 fn spawn_stealing_task<'scope, F>(
     scope: &Scope<'scope>,
     slave_folder: &'scope F,
-    min_size: usize,
-    max_size: usize,
-) -> SmallSender<AtomicLink<(Option<F::Output>, Option<F::Input>)>>
+    stolen_stuffs: &'scope AtomicList<Stolen<F>>,
+    help: HelpParameters<'scope, RetrievalCut<F::Input>>,
+) -> SmallSender<AtomicLink<Stolen<F>>>
 where
     F: Folder + 'scope + Send,
-    F::Input: DivisibleIntoBlocks + 'scope,
+    F::Input: 'scope,
 {
     let (sender, receiver) = small_channel();
-    scope.spawn(move |s| {
-        let stolen_input: Option<AtomicLink<(Option<F::Output>, Option<F::Input>)>>;
+    backend::spawn_in(scope, move |s| {
+        let _place = match help.limit.enter() {
+            Some(place) => place,
+            // no place left : the victim goes on alone
+            None => return,
+        };
+        let _depth = DepthGuard::task(true);
+        let stolen_node: Option<AtomicLink<Stolen<F>>>;
+        record(slave_folder, SchedulingEvent::StealRequest);
         #[cfg(feature = "logs")]
         {
-            stolen_input = rayon_logs::subgraph("slave wait", 1, || receiver.recv());
+            stolen_node = rayon_logs::subgraph("slave wait", 1, || {
+                wait_for(slave_folder, || receiver.recv())
+            });
         }
         #[cfg(not(feature = "logs"))]
         {
-            stolen_input = receiver.recv();
-        }
-        if stolen_input.is_none() {
-            return;
+            stolen_node = wait_for(slave_folder, || receiver.recv());
         }
-        slave_work(s, stolen_input.unwrap(), slave_folder, min_size, max_size)
+        let completion = match stolen_node {
+            Some(node) => Completion::new(node),
+            None => return,
+        };
+        record(slave_folder, SchedulingEvent::Steal);
+        slave_work(s, completion, slave_folder, stolen_stuffs, help)
     });
     sender
 }
@@ -546,20 +1056,20 @@ fn master_work<'scope, F, O1, FOLD1>(
     input: F::Input,
     fold: &FOLD1,
     slave_folder: &'scope F,
-    stolen_stuffs: &AtomicList<(Option<F::Output>, Option<F::Input>)>,
-    min_size: usize,
-    max_size: usize,
+    stolen_stuffs: &'scope AtomicList<Stolen<F>>,
+    help: HelpParameters<'scope, RetrievalCut<F::Input>>,
 ) -> O1
 where
     F: Folder + 'scope + Send,
-    F::Input: DivisibleIntoBlocks + 'scope,
+    F::Input: 'scope,
     O1: Send,
     FOLD1: Fn(O1, F::Input, usize) -> (O1, F::Input),
 {
+    let (min_size, max_size) = (help.min_size, help.max_size);
     let mut input = input;
     let mut current_output = init;
     loop {
-        let sender = spawn_stealing_task(scope, slave_folder, min_size, max_size);
+        let sender = spawn_stealing_task(scope, slave_folder, stolen_stuffs, help);
         // let's work sequentially until stolen
         match powers(min_size)
             .take_while(|&p| p < max_size)
@@ -568,7 +1078,7 @@ where
             .try_fold((current_output, input), |(output, input), size| {
                 let checked_size = min(input.base_length(), size); //TODO: remove all these mins
                 if checked_size > 0 {
-                    Ok(fold(output, input, checked_size))
+                    Ok(master_fold(fold, slave_folder, output, input, checked_size))
                 } else {
                     Err(output)
                 }
@@ -576,7 +1086,17 @@ where
             Ok((output, remaining_input)) => {
                 if remaining_input.base_length() > min_size {
                     let (my_half, his_half) = remaining_input.divide();
+                    record(slave_folder, SchedulingEvent::Split);
                     if his_half.base_length() > 0 {
+                        // helpers do not tell us where they come from
+                        record(
+                            slave_folder,
+                            SchedulingEvent::StealAnswer {
+                                given: his_half.base_length(),
+                                kept: my_half.base_length(),
+                                remote: false,
+                            },
+                        );
                         let stolen_node = stolen_stuffs.push_front((None, Some(his_half)));
                         sender.send(stolen_node);
                     }
@@ -584,7 +1104,7 @@ where
                     current_output = output;
                 } else {
                     let length = remaining_input.base_length();
-                    return fold(output, remaining_input, length).0;
+                    return master_fold(fold, slave_folder, output, remaining_input, length).0;
                 }
             }
             Err(output) => return output,
@@ -592,27 +1112,59 @@ where
     }
 }
 
+/// Fold a block with the master's fold, telling the slaves' folder how much got done.
+fn master_fold<F, O1, FOLD1>(
+    fold: &FOLD1,
+    slave_folder: &F,
+    output: O1,
+    input: F::Input,
+    size: usize,
+) -> (O1, F::Input)
+where
+    F: Folder,
+    FOLD1: Fn(O1, F::Input, usize) -> (O1, F::Input),
+{
+    check_cancellation();
+    let initial_length = input.base_length();
+    #[cfg(feature = "trace")]
+    let start = Instant::now();
+    let (output, remaining_input) = fold(output, input, size);
+    let folded = initial_length.saturating_sub(remaining_input.base_length());
+    #[cfg(feature = "trace")]
+    record(
+        slave_folder,
+        SchedulingEvent::MasterFold(folded, start.elapsed()),
+    );
+    slave_folder.processed(folded);
+    (output, remaining_input)
+}
+
 //TODO: we could maybe avoid code duplication between master and slave with a dummy head of the
 //list for the master
 fn slave_work<'scope, F>(
     scope: &Scope<'scope>,
-    node: AtomicLink<(Option<F::Output>, Option<F::Input>)>,
+    completion: Completion<Stolen<F>>,
     slave_folder: &'scope F,
-    min_size: usize,
-    max_size: usize,
+    stolen_stuffs: &'scope AtomicList<Stolen<F>>,
+    help: HelpParameters<'scope, RetrievalCut<F::Input>>,
 ) where
     F: Folder + 'scope + Send,
-    F::Input: DivisibleIntoBlocks + 'scope,
+    F::Input: 'scope,
 {
+    let (min_size, max_size) = (help.min_size, help.max_size);
+    let node = completion.node().clone();
     let mut input = node.take().unwrap().1.unwrap();
     let mut o2 = slave_folder.identity();
+    let token = CurrentToken::get();
     loop {
-        let sender = spawn_stealing_task(scope, slave_folder, min_size, max_size);
+        let sender = spawn_stealing_task(scope, slave_folder, stolen_stuffs, help);
         // let's work sequentially until stolen
         match powers(min_size)
             .take_while(|&p| p < max_size)
             .chain(repeat(max_size))
-            .take_while(|_| !sender.receiver_is_waiting() && !node.requested())
+            .take_while(|_| {
+                !sender.receiver_is_waiting() && !node.requested() && !token.is_cancelled()
+            })
             .try_fold((o2, input), |(output2, input), size| {
                 let checked_size = min(input.base_length(), size); //TODO: remove all these mins
                 if checked_size > 0 {
@@ -622,10 +1174,22 @@ fn slave_work<'scope, F>(
                 }
             }) {
             Ok((output2, remaining_input)) => {
-                if node.requested() {
+                if token.is_cancelled() {
+                    // we cannot unwind : the master might be waiting for us.
+                    // it stops at its next block anyway.
+                    completion
+                        .complete((Some(slave_folder.to_output(output2, remaining_input)), None));
+                    return;
+                } else if node.requested() {
                     // retrieval operations are prioritized over steal ops
-                    let (completed, remaining_input) = remaining_input.divide_at(0);
-                    node.replace((
+                    let (to_finish, remaining_input) = (help.retrieval_cut)(remaining_input);
+                    let length = to_finish.base_length();
+                    let (output2, completed) = if length > 0 {
+                        slave_folder.fold(output2, to_finish, length)
+                    } else {
+                        (output2, to_finish)
+                    };
+                    completion.complete((
                         Some(slave_folder.to_output(output2, completed)),
                         Some(remaining_input),
                     ));
@@ -635,9 +1199,18 @@ fn slave_work<'scope, F>(
                     let length = remaining_input.base_length();
                     if length > min_size {
                         let (my_half, his_half) = remaining_input.divide();
+                        record(slave_folder, SchedulingEvent::Split);
                         // TODO: have an empty method
                         if his_half.base_length() > 0 {
-                            let stolen_node = (&node).split((None, Some(his_half)));
+                            record(
+                                slave_folder,
+                                SchedulingEvent::StealAnswer {
+                                    given: his_half.base_length(),
+                                    kept: my_half.base_length(),
+                                    remote: false,
+                                },
+                            );
+                            let stolen_node = node.split((None, Some(his_half)));
                             sender.send(stolen_node)
                         }
                         input = my_half;
@@ -646,7 +1219,7 @@ fn slave_work<'scope, F>(
                         // just fold it locally
                         let (intermediate_output, input) =
                             slave_folder.fold(output2, remaining_input, length);
-                        node.replace((
+                        completion.complete((
                             Some(slave_folder.to_output(intermediate_output, input)),
                             None,
                         ));
@@ -655,9 +1228,254 @@ fn slave_work<'scope, F>(
                 }
             }
             Err((output2, input)) => {
-                node.replace((Some(slave_folder.to_output(output2, input)), None));
+                completion.complete((Some(slave_folder.to_output(output2, input)), None));
                 return;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        capped_max_block_size, default_max_block_size, default_min_block_size, remote_split,
+        schedule_join_context_max_size,
+    };
+    use crate::folders::fold::Fold;
+    use crate::nesting::sequential_depth;
+    use crate::prelude::*;
+    use crate::{max_block_size, BasicPower, Policy};
+    use std::iter::repeat;
+    use std::marker::PhantomData;
+    use std::ops::Range;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    /// Panic payload of computations failing on purpose.
+    struct FailingOnPurpose;
+
+    /// Deepest sequential depth seen by a nested computation.
+    fn nested_depth() -> usize {
+        (0..100)
+            .into_adapt_iter()
+            .with_policy(Policy::Join(10))
+            .fold(|| 0, |d, _| d.max(sequential_depth()))
+            .reduce(|d1, d2| d1.max(d2))
+    }
+
+    #[test]
+    fn panics_restore_sequential_depth() {
+        // with one thread nothing is ever stolen so we always end up in sequential blocks
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .expect("pool build failed");
+        pool.install(|| {
+            let folder = Fold {
+                identity_op: || (),
+                fold_op: |_, r: Range<usize>, limit: usize| {
+                    if sequential_depth() == 1 {
+                        assert_eq!(nested_depth(), 1);
+                        std::panic::panic_any(FailingOnPurpose);
+                    }
+                    ((), (r.start + limit)..r.end)
+                },
+                phantom: PhantomData,
+            };
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                schedule_join_context_max_size(0..1_000, &folder, &|_, _| (), 10, 100)
+            }));
+            assert!(result
+                .expect_err("no sequential block")
+                .is::<FailingOnPurpose>());
+            assert_eq!(sequential_depth(), 0);
+            assert_eq!(nested_depth(), 0);
+        })
+    }
+    #[test]
+    fn steal_handshakes_stress() {
+        let expected: Vec<usize> = (0..100_000).collect();
+        for threads in &[1, 2, 3, 4, 8, 16] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(*threads)
+                .build()
+                .expect("pool build failed");
+            pool.install(|| {
+                for _ in 0..50 {
+                    let sum = (0..100_000)
+                        .into_adapt_iter()
+                        .with_policy(Policy::Adaptive(10, 100))
+                        .fold(|| 0, |s, e| s + e)
+                        .reduce(|s1, s2| s1 + s2);
+                    assert_eq!(sum, 4_999_950_000);
+                    let v = (0..100_000)
+                        .into_adapt_iter()
+                        .with_policy(Policy::Adaptive(10, 100))
+                        .by_blocks(repeat(30_000))
+                        .fold(Vec::new, |mut v, e| {
+                            v.push(e);
+                            v
+                        })
+                        .helping_fold(
+                            Vec::new(),
+                            |mut v, e| {
+                                v.push(e);
+                                v
+                            },
+                            |mut v, v2| {
+                                v.extend(v2);
+                                v
+                            },
+                        );
+                    assert_eq!(v, expected);
+                }
+            })
+        }
+    }
+    #[test]
+    fn helping_with_all_policies() {
+        let expected: Vec<usize> = (0..10_000).collect();
+        for policy in &[
+            Policy::DefaultPolicy,
+            Policy::Sequential,
+            Policy::Join(100),
+            Policy::JoinContext(100),
+            Policy::DepJoin(100),
+            Policy::Adaptive(10, 100),
+            Policy::Rayon,
+            Policy::Numa(10, 100),
+        ] {
+            let v = (0..10_000)
+                .into_adapt_iter()
+                .with_policy(*policy)
+                .by_blocks(repeat(3_000))
+                .fold(Vec::new, |mut v, e| {
+                    v.push(e);
+                    v
+                })
+                .helping_fold(
+                    Vec::new(),
+                    |mut v, e| {
+                        v.push(e);
+                        v
+                    },
+                    |mut v, v2| {
+                        v.extend(v2);
+                        v
+                    },
+                );
+            assert_eq!(v, expected);
+        }
+    }
+
+    /// A range we can only divide in halves.
+    struct Halves(Range<usize>);
+
+    impl Divisible for Halves {
+        type Power = BasicPower;
+        fn base_length(&self) -> usize {
+            self.0.len()
+        }
+        fn divide(self) -> (Self, Self) {
+            let middle = (self.0.start + self.0.end) / 2;
+            (Halves(self.0.start..middle), Halves(middle..self.0.end))
+        }
+    }
+
+    #[test]
+    fn helping_with_basic_inputs() {
+        let expected: Vec<usize> = (0..100_000).collect();
+        let push_all = |mut v: Vec<usize>, h: Halves, limit: usize| {
+            let end = h.0.start + limit;
+            v.extend(h.0.start..end);
+            (v, Halves(end..h.0.end))
+        };
+        for policy in &[
+            Policy::DefaultPolicy,
+            Policy::Sequential,
+            Policy::Join(100),
+            Policy::Adaptive(10, 100),
+        ] {
+            for _ in 0..20 {
+                let v = Halves(0..100_000)
+                    .with_policy(*policy)
+                    .partial_fold(Vec::new, push_all)
+                    .helping_partial_fold(Vec::new(), push_all, |mut v, v2| {
+                        v.extend(v2);
+                        v
+                    });
+                assert_eq!(v, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn refused_thieves_do_not_grow_the_stack() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .stack_size(64 * 1024)
+            .build()
+            .expect("pool build failed");
+        // the only place is taken : all thieves get refused, one block at a time
+        let sum = pool.install(|| {
+            (0..1_000_000)
+                .with_policy(Policy::Adaptive(1, 1))
+                .with_max_threads(1)
+                .partial_fold(
+                    || 0,
+                    |s, r, limit| {
+                        let (todo, remaining) = r.divide_at(limit);
+                        (s + todo.sum::<usize>(), remaining)
+                    },
+                )
+                .reduce(|a, b| a + b)
+        });
+        assert_eq!(sum, 499_999_500_000);
+    }
+
+    #[test]
+    fn remote_thieves_get_a_quarter() {
+        assert_eq!(remote_split(0..1_000), (0..500, 500..750, 750..1_000));
+    }
+
+    #[test]
+    fn block_sizes_fit_in_cache() {
+        let n = 1_000_000;
+        // 10 * sqrt(n) by default
+        assert_eq!(capped_max_block_size(None)(n), 10_000);
+        assert_eq!(capped_max_block_size(Some(1_000_000))(n), 10_000);
+        assert_eq!(capped_max_block_size(Some(5_000))(n), 5_000);
+        // we never go below the min block size
+        let min_size = default_min_block_size(n);
+        assert!(min_size > 1 && min_size < 5_000);
+        assert_eq!(capped_max_block_size(Some(1))(n), min_size);
+        assert_eq!(default_max_block_size(n), 10_000);
+    }
+
+    #[test]
+    fn iterators_fit_blocks_in_cache() {
+        let activated = (0..1_000).into_adapt_iter().fold(|| 0, |s, e| s + e);
+        assert_eq!(activated.cache_block_size, Some(max_block_size::<usize>()));
+        let activated = (0..1_000)
+            .into_adapt_iter()
+            .map(|e| [e; 16])
+            .fold(|| 0, |s, e| s + e[0]);
+        assert_eq!(
+            activated.cache_block_size,
+            Some(max_block_size::<[usize; 16]>())
+        );
+        assert_eq!(activated.reduce(|a, b| a + b), 499_500);
+        let activated = (0..1_000).with_policy(Policy::Join(10)).partial_fold(
+            || 0,
+            |s, r, limit| {
+                let (todo, remaining) = r.divide_at(limit);
+                (s + todo.sum::<usize>(), remaining)
+            },
+        );
+        // we know nothing about the elements of basic inputs
+        assert_eq!(activated.cache_block_size, None);
+        assert_eq!(
+            activated.fitting_in_cache::<usize>().cache_block_size,
+            Some(max_block_size::<usize>())
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! One shot notification between two threads.
+//! The waiting side first spins with an exponential backoff, then yields and finally parks
+//! until the notifying side unparks it.
+//! This way we stay reactive on short waits but do not burn whole cores when the thread we
+//! wait for is descheduled (oversubscribed pools).
+use crossbeam::atomic::AtomicCell;
+use crossbeam::utils::Backoff;
+use std::sync::atomic::{fence, AtomicBool, Ordering};
+use std::thread::{self, Thread};
+
+pub(crate) struct Signal {
+    done: AtomicBool,
+    sleeper: AtomicCell<Option<Thread>>,
+}
+
+impl Signal {
+    pub(crate) fn new() -> Self {
+        Signal {
+            done: AtomicBool::new(false),
+            sleeper: AtomicCell::new(None),
+        }
+    }
+
+    /// Return whether `notify` was already called.
+    pub(crate) fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+
+    /// Mark the signal as done and wake up the waiting thread (if any).
+    pub(crate) fn notify(&self) {
+        self.done.store(true, Ordering::SeqCst);
+        // pairs with the fence in `wait`: either the waiter sees us done
+        // or we see the waiter registered.
+        fence(Ordering::SeqCst);
+        if let Some(sleeper) = self.sleeper.take() {
+            sleeper.unpark()
+        }
+    }
+
+    /// Block until `notify` is called.
+    /// Note that rayon does not expose any way to run pending jobs from here
+    /// so we cannot help while waiting: we park instead.
+    pub(crate) fn wait(&self) {
+        let backoff = Backoff::new();
+        while !self.is_done() {
+            if backoff.is_completed() {
+                self.sleeper.store(Some(thread::current()));
+                fence(Ordering::SeqCst);
+                // parking might return spuriously, hence the loop
+                while !self.is_done() {
+                    thread::park();
+                }
+            } else {
+                backoff.snooze();
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::signal::Signal;
 use crossbeam::atomic::AtomicCell;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -5,6 +6,7 @@ use std::sync::Arc;
 struct SmallChannel<T> {
     request: AtomicBool,
     data: AtomicCell<Option<T>>,
+    sent: Signal,
 }
 
 pub struct SmallSender<T> {
@@ -20,6 +22,7 @@ impl<T> SmallChannel<T> {
         SmallChannel {
             request: AtomicBool::new(false),
             data: AtomicCell::new(None),
+            sent: Signal::new(),
         }
     }
 }
@@ -36,16 +39,12 @@ pub fn small_channel<T: Send>() -> (SmallSender<T>, SmallReceiver<T>) {
 }
 
 impl<T> SmallReceiver<T> {
+    /// Block until the sender sends something or is dropped.
+    /// We spin a little, then yield and finally park until the sender wakes us up.
     pub fn recv(self) -> Option<T> {
         self.channel.request.store(true, Ordering::Relaxed);
-        let mut channel = self.channel;
-        loop {
-            let r = Arc::try_unwrap(channel);
-            match r {
-                Ok(c) => return c.data.into_inner(),
-                Err(ac) => channel = ac,
-            }
-        }
+        self.channel.sent.wait();
+        self.channel.data.take()
     }
 }
 
@@ -56,5 +55,36 @@ impl<T> SmallSender<T> {
     }
     pub fn send(self, t: T) {
         self.channel.data.store(Some(t));
+        // dropping self now wakes up the receiver
+    }
+}
+
+impl<T> Drop for SmallSender<T> {
+    fn drop(&mut self) {
+        self.channel.sent.notify()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+    #[test]
+    fn late_sender_wakes_receiver() {
+        let (sender, receiver) = small_channel();
+        let handle = thread::spawn(move || receiver.recv());
+        while !sender.receiver_is_waiting() {
+            thread::yield_now();
+        }
+        thread::sleep(Duration::from_millis(50)); // let the receiver park
+        sender.send(3);
+        assert_eq!(handle.join().unwrap(), Some(3));
+
+        let (sender, receiver) = small_channel::<u32>();
+        let handle = thread::spawn(move || receiver.recv());
+        thread::sleep(Duration::from_millis(50));
+        std::mem::drop(sender);
+        assert_eq!(handle.join().unwrap(), None);
     }
 }
@@ -0,0 +1,245 @@
+//! Lightweight scheduling statistics (`stats` feature).
+//! A `SchedulingStats` collector can be shared by any number of computations
+//! (for example all computations of a thread pool) and read at any time.
+use crate::activated_input::ActivatedInput;
+use crate::{Divisible, Folder, SchedulingEvent};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Counters for all scheduling events of computations attached to us.
+///
+/// # Example
+///
+/// ```
+/// use rayon_adaptive::prelude::*;
+/// use rayon_adaptive::{Policy, SchedulingStats};
+/// let stats = SchedulingStats::new();
+/// let sum = (0..100_000)
+///     .into_adapt_iter()
+///     .with_policy(Policy::Join(1_000))
+///     .fold(|| 0, |s, e| s + e)
+///     .with_stats(&stats)
+///     .reduce(|s1, s2| s1 + s2);
+/// assert_eq!(sum, 4_999_950_000);
+/// assert_eq!(stats.blocks(), stats.splits() + 1);
+/// assert_eq!(stats.reductions(), stats.splits());
+/// println!("{}", stats);
+/// ```
+pub struct SchedulingStats {
+    steals: AtomicUsize,
+    splits: AtomicUsize,
+    blocks: AtomicUsize,
+    block_sizes: Vec<AtomicUsize>,
+    reductions: AtomicUsize,
+    waiting_nanos: AtomicU64,
+}
+
+impl Default for SchedulingStats {
+    fn default() -> Self {
+        SchedulingStats::new()
+    }
+}
+
+impl SchedulingStats {
+    pub fn new() -> Self {
+        SchedulingStats {
+            steals: AtomicUsize::new(0),
+            splits: AtomicUsize::new(0),
+            blocks: AtomicUsize::new(0),
+            // block sizes are counted by powers of two (0 gets its own class)
+            block_sizes: (0..=0usize.leading_zeros())
+                .map(|_| AtomicUsize::new(0))
+                .collect(),
+            reductions: AtomicUsize::new(0),
+            waiting_nanos: AtomicU64::new(0),
+        }
+    }
+    /// How many tasks were executed by another thread than their creator.
+    pub fn steals(&self) -> usize {
+        self.steals.load(Ordering::Relaxed)
+    }
+    /// How many times inputs got divided in two.
+    pub fn splits(&self) -> usize {
+        self.splits.load(Ordering::Relaxed)
+    }
+    /// How many blocks got folded.
+    pub fn blocks(&self) -> usize {
+        self.blocks.load(Ordering::Relaxed)
+    }
+    /// Distribution of folded blocks sizes.
+    /// Return for each size class `[2^k, 2^(k+1))` (and 0) with at least one block,
+    /// its lower bound and the number of blocks in it.
+    pub fn block_sizes(&self) -> Vec<(usize, usize)> {
+        self.block_sizes
+            .iter()
+            .enumerate()
+            .map(|(class, count)| {
+                let lower_bound = if class == 0 { 0 } else { 1 << (class - 1) };
+                (lower_bound, count.load(Ordering::Relaxed))
+            })
+            .filter(|&(_, count)| count > 0)
+            .collect()
+    }
+    /// How many outputs got reduced or retrieved.
+    pub fn reductions(&self) -> usize {
+        self.reductions.load(Ordering::Relaxed)
+    }
+    /// Total time threads spent blocked, waiting for other threads.
+    pub fn waiting_time(&self) -> Duration {
+        Duration::from_nanos(self.waiting_nanos.load(Ordering::Relaxed))
+    }
+    fn count_block(&self, size: usize) {
+        let class = (0usize.leading_zeros() - size.leading_zeros()) as usize;
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+        self.block_sizes[class].fetch_add(1, Ordering::Relaxed);
+    }
+    fn record(&self, event: SchedulingEvent) {
+        let counter = match event {
+            SchedulingEvent::Split => &self.splits,
+            SchedulingEvent::Steal => &self.steals,
+            SchedulingEvent::Reduction => &self.reductions,
+            SchedulingEvent::Wait(duration) => {
+                self.waiting_nanos
+                    .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+                return;
+            }
+            SchedulingEvent::StealRequest
+            | SchedulingEvent::StealAnswer { .. }
+            | SchedulingEvent::MasterFold(..) => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for SchedulingStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "steals: {}", self.steals())?;
+        writeln!(f, "splits: {}", self.splits())?;
+        writeln!(f, "reductions: {}", self.reductions())?;
+        writeln!(f, "waiting time: {:?}", self.waiting_time())?;
+        writeln!(f, "blocks: {}", self.blocks())?;
+        for (lower_bound, count) in self.block_sizes() {
+            writeln!(f, "  size >= {}: {}", lower_bound, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Folder recording all scheduling events in a `SchedulingStats`.
+#[must_use = "folders are lazy and do nothing unless consumed"]
+pub struct WithStats<'a, F> {
+    pub(crate) inner_folder: F,
+    pub(crate) stats: &'a SchedulingStats,
+}
+
+impl<'a, F: Folder> Folder for WithStats<'a, F> {
+    type Input = F::Input;
+    type IntermediateOutput = F::IntermediateOutput;
+    type Output = F::Output;
+    fn identity(&self) -> Self::IntermediateOutput {
+        self.inner_folder.identity()
+    }
+    fn fold(
+        &self,
+        io: Self::IntermediateOutput,
+        i: Self::Input,
+        limit: usize,
+    ) -> (Self::IntermediateOutput, Self::Input) {
+        let initial_length = i.base_length();
+        let (io, remaining) = self.inner_folder.fold(io, i, limit);
+        self.stats
+            .count_block(initial_length.saturating_sub(remaining.base_length()));
+        (io, remaining)
+    }
+    fn to_output(&self, io: Self::IntermediateOutput, i: Self::Input) -> Self::Output {
+        self.inner_folder.to_output(io, i)
+    }
+    fn processed(&self, size: usize) {
+        self.stats.count_block(size);
+        self.inner_folder.processed(size)
+    }
+    fn record(&self, event: SchedulingEvent) {
+        self.stats.record(event);
+        self.inner_folder.record(event)
+    }
+}
+
+impl<'p, F: Folder, S, P> ActivatedInput<'p, F, S, P> {
+    /// Record all scheduling events in given statistics.
+    pub fn with_stats(self, stats: &SchedulingStats) -> ActivatedInput<'p, WithStats<'_, F>, S, P> {
+        ActivatedInput {
+            input: self.input,
+            folder: WithStats {
+                inner_folder: self.folder,
+                stats,
+            },
+            policy: self.policy,
+            sizes: self.sizes,
+            max_threads: self.max_threads,
+            pool: self.pool,
+            cache_block_size: self.cache_block_size,
+            power: self.power,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SchedulingStats;
+    use crate::prelude::*;
+    use crate::Policy;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn steals_and_blocks_get_counted() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .expect("pool build failed");
+        for policy in &[Policy::Join(100), Policy::Adaptive(10, 100)] {
+            let stats = SchedulingStats::new();
+            let sum = pool.install(|| {
+                (0..10_000)
+                    .into_adapt_iter()
+                    .with_policy(*policy)
+                    .fold(
+                        || 0,
+                        |s, e| {
+                            thread::sleep(Duration::from_micros(10));
+                            s + e
+                        },
+                    )
+                    .with_stats(&stats)
+                    .reduce(|s1, s2| s1 + s2)
+            });
+            assert_eq!(sum, 49_995_000);
+            // slow blocks leave idle threads enough time to steal
+            assert!(stats.steals() > 0);
+            assert!(stats.blocks() > 1);
+            assert_eq!(
+                stats
+                    .block_sizes()
+                    .iter()
+                    .map(|&(_, count)| count)
+                    .sum::<usize>(),
+                stats.blocks()
+            );
+            assert!(stats
+                .block_sizes()
+                .iter()
+                .all(|&(lower_bound, _)| lower_bound > 0 && lower_bound <= 100));
+        }
+        // Join blocks are exactly the leaves of the division tree
+        let stats = SchedulingStats::new();
+        (0..10_000)
+            .into_adapt_iter()
+            .with_policy(Policy::Join(100))
+            .fold(|| 0, |s, e| s + e)
+            .with_stats(&stats)
+            .reduce(|s1, s2| s1 + s2);
+        assert_eq!(stats.blocks(), stats.splits() + 1);
+        assert_eq!(stats.reductions(), stats.splits());
+    }
+}
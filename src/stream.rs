@@ -0,0 +1,154 @@
+//! Ordered stream of outputs, computed ahead of the consumer.
+//! Each macro-block is scheduled in its own task as soon as there is room
+//! in the lookahead window. The consumer gets outputs of block k
+//! while blocks k+1 and later are being computed.
+//! Panics of blocks propagate to the consumer when it reaches them.
+use crate::backend;
+use crate::nesting::DepthGuard;
+use crate::outputs::{concatenate, gathering, GatheringFolder, Outputs, OutputsIter};
+use crate::prelude::*;
+use crate::scheduling::{schedule, SchedulingParameters};
+use crate::smallchannel::{small_channel, SmallReceiver};
+use crate::{DivisibleIntoBlocks, Folder};
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::mem::transmute;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+
+/// Background task computing a block.
+type BlockTask<'a> = Box<dyn FnOnce() + Send + 'a>;
+
+/// Stream of outputs, possibly borrowing data living for `'a`.
+/// Dropping it waits for all blocks still being computed.
+pub struct OrderedStream<'a, F: Folder, S> {
+    remaining_input: F::Input,
+    folder: Arc<GatheringFolder<F>>,
+    sizes: S,
+    parameters: SchedulingParameters<'a>,
+    lookahead: usize,
+    pending_blocks: VecDeque<SmallReceiver<thread::Result<Outputs<F::Output>>>>,
+    current_block: Option<OutputsIter<F::Output>>,
+    borrowed: PhantomData<&'a ()>,
+}
+
+impl<'a, F, S> OrderedStream<'a, F, S>
+where
+    F: Folder + Send + 'a,
+    F::Input: DivisibleIntoBlocks + 'a,
+    F::Output: 'a,
+    S: Iterator<Item = usize>,
+{
+    /// Create a stream. Unless `'a` is `'static` it must be dropped before `'a` ends
+    /// so only hand out borrows of it.
+    pub(crate) fn new(
+        input: F::Input,
+        folder: F,
+        parameters: SchedulingParameters<'a>,
+        sizes: S,
+        lookahead: usize,
+    ) -> Self {
+        assert!(lookahead > 0, "we need to compute at least one block ahead");
+        OrderedStream {
+            remaining_input: input,
+            folder: Arc::new(gathering(folder)),
+            sizes,
+            parameters,
+            lookahead,
+            pending_blocks: VecDeque::new(),
+            current_block: None,
+            borrowed: PhantomData,
+        }
+    }
+
+    /// Start computing blocks until the lookahead window is full.
+    fn fill_window(&mut self) {
+        while self.pending_blocks.len() < self.lookahead && self.remaining_input.base_length() > 0 {
+            let next_size = min(
+                self.sizes.next().expect("not enough sizes for chunks"),
+                self.remaining_input.base_length(),
+            );
+            let block = self.remaining_input.cut_left_at(next_size);
+            let folder = self.folder.clone();
+            let parameters = self.parameters;
+            let (sender, receiver) = small_channel();
+            let task: BlockTask<'a> = Box::new(move || {
+                let _depth = DepthGuard::task(true);
+                sender.send(catch_unwind(AssertUnwindSafe(|| {
+                    schedule(block, folder.as_ref(), &concatenate, parameters)
+                })))
+            });
+            // this is ok since we wait for all tasks before being dropped, so before 'a ends
+            let task: BlockTask<'static> = unsafe { transmute(task) };
+            backend::spawn(task);
+            self.pending_blocks.push_back(receiver);
+        }
+    }
+}
+
+impl<'a, F, S> Iterator for OrderedStream<'a, F, S>
+where
+    F: Folder + Send + 'a,
+    F::Input: DivisibleIntoBlocks + 'a,
+    F::Output: 'a,
+    S: Iterator<Item = usize>,
+{
+    type Item = F::Output;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(output) = self.current_block.as_mut().and_then(|block| block.next()) {
+                return Some(output);
+            }
+            self.fill_window();
+            let receiver = self.pending_blocks.pop_front()?;
+            // refill now so that we keep computing while the caller consumes this block
+            self.fill_window();
+            match receiver.recv().expect("blocks always send their outputs") {
+                Ok(outputs) => self.current_block = Some(outputs.into_iter()),
+                Err(panic) => resume_unwind(panic),
+            }
+        }
+    }
+}
+
+impl<'a, F: Folder, S> Drop for OrderedStream<'a, F, S> {
+    fn drop(&mut self) {
+        // blocks might borrow data living only for 'a
+        for receiver in self.pending_blocks.drain(..) {
+            let _ = receiver.recv();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use std::iter::repeat;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    /// Panic payload of computations failing on purpose.
+    struct FailingOnPurpose;
+
+    #[test]
+    fn ordered_streams_propagate_panics() {
+        let v: Vec<usize> = (0..10_000).collect();
+        let streamed = catch_unwind(AssertUnwindSafe(|| {
+            v.as_slice()
+                .into_adapt_iter()
+                .by_blocks(repeat(1_000))
+                .fold(Vec::new, |mut v, e| {
+                    if *e == 5_000 {
+                        std::panic::panic_any(FailingOnPurpose);
+                    }
+                    v.push(*e);
+                    v
+                })
+                .scoped_ordered_stream(2, |stream| stream.flatten().count())
+        }));
+        assert!(streamed
+            .expect_err("no block failed")
+            .is::<FailingOnPurpose>());
+    }
+}
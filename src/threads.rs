@@ -0,0 +1,114 @@
+//! Restricting how many threads of the pool take part in a computation.
+//! Thieves joining an adaptive computation first take a place.
+//! Once all places are taken they leave and go on with other tasks of the pool.
+use crate::backend::current_num_threads;
+use std::cmp::{max, min};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Places available for the threads of one computation.
+pub(crate) struct ThreadsLimit {
+    /// None if the whole pool can take part.
+    max_threads: Option<usize>,
+    active_threads: AtomicUsize,
+}
+
+impl ThreadsLimit {
+    /// Create places for given number of threads (the whole pool if none).
+    /// The thread starting the computation already holds one.
+    pub(crate) fn new(max_threads: Option<usize>) -> Self {
+        ThreadsLimit {
+            max_threads: max_threads.map(|threads| max(min(threads, current_num_threads()), 1)),
+            active_threads: AtomicUsize::new(1),
+        }
+    }
+    /// How many threads can work on the computation at once.
+    pub(crate) fn max_threads(&self) -> usize {
+        self.max_threads.unwrap_or_else(current_num_threads)
+    }
+    /// Try taking a place for a thief. It is freed when the returned guard drops.
+    pub(crate) fn enter(&self) -> Option<Participation<'_>> {
+        let max_threads = match self.max_threads {
+            Some(max_threads) => max_threads,
+            None => {
+                return Some(Participation {
+                    active_threads: None,
+                })
+            }
+        };
+        let mut active_threads = self.active_threads.load(Ordering::Relaxed);
+        loop {
+            if active_threads >= max_threads {
+                return None;
+            }
+            match self.active_threads.compare_exchange_weak(
+                active_threads,
+                active_threads + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Some(Participation {
+                        active_threads: Some(&self.active_threads),
+                    })
+                }
+                Err(current) => active_threads = current,
+            }
+        }
+    }
+}
+
+/// A place taken by a thief.
+pub(crate) struct Participation<'a> {
+    active_threads: Option<&'a AtomicUsize>,
+}
+
+impl<'a> Drop for Participation<'a> {
+    fn drop(&mut self) {
+        if let Some(active_threads) = self.active_threads {
+            active_threads.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::Policy;
+    use std::ops::Range;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn max_threads_limit_concurrent_workers() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(8)
+            .build()
+            .expect("pool build failed");
+        let active = AtomicUsize::new(0);
+        let most_active = AtomicUsize::new(0);
+        let busy_fold = |s: usize, r: Range<usize>, limit: usize| {
+            let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+            most_active.fetch_max(now_active, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_micros(50));
+            let (todo, remaining) = r.divide_at(limit);
+            active.fetch_sub(1, Ordering::SeqCst);
+            (s + todo.sum::<usize>(), remaining)
+        };
+        pool.install(|| {
+            for policy in &[Policy::DefaultPolicy, Policy::Adaptive(10, 100)] {
+                let sum = (0..10_000)
+                    .with_policy(*policy)
+                    .with_max_threads(2)
+                    .partial_fold(|| 0, busy_fold)
+                    .reduce(|a, b| a + b);
+                assert_eq!(sum, 49_995_000);
+                let sum = (0..10_000)
+                    .with_policy(*policy)
+                    .with_max_threads(3)
+                    .partial_fold(|| 0, busy_fold)
+                    .helping_partial_fold(0, busy_fold, |a, b| a + b);
+                assert_eq!(sum, 49_995_000);
+            }
+        });
+        assert!(most_active.load(Ordering::SeqCst) <= 3);
+    }
+}
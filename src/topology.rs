@@ -0,0 +1,109 @@
+//! Machine topology (cores, NUMA nodes, caches) as reported by hwloc.
+//! We use it to size blocks so that they fit in caches.
+//! When hwloc reports nothing we fall back to conservative defaults.
+use crate::backend::current_num_threads;
+use hwloc::{ObjectType, Topology};
+use std::cmp::max;
+use std::mem;
+use std::sync::OnceLock;
+
+/// Per-core cache size we assume when hwloc cannot tell us (256kb).
+const FALLBACK_CORE_CACHE_SIZE: usize = 256 * 1024;
+/// Shared cache size per thread we assume when hwloc cannot tell us (1mb).
+const FALLBACK_SHARED_CACHE_SIZE: usize = 1024 * 1024;
+
+/// What we know about the machine we run on.
+///
+/// # Example
+///
+/// ```
+/// use rayon_adaptive::{max_block_size, MachineTopology};
+/// let topology = MachineTopology::current();
+/// assert!(topology.cores > 0 && topology.numa_nodes > 0);
+/// // a block of u64 fits in the cache of one core
+/// assert!(max_block_size::<u64>() * 8 <= topology.core_cache_size());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MachineTopology {
+    /// Number of physical cores.
+    pub cores: usize,
+    /// Number of NUMA nodes (1 on non-NUMA machines).
+    pub numa_nodes: usize,
+    /// Size in bytes of the smallest L2 cache (if any).
+    pub l2_cache_size: Option<usize>,
+    /// Total size in bytes of all L3 caches (if any).
+    pub l3_cache_size: Option<usize>,
+}
+
+/// Topology of the machine, loaded on first use.
+static TOPOLOGY: OnceLock<MachineTopology> = OnceLock::new();
+
+impl MachineTopology {
+    fn load() -> Self {
+        let topology = Topology::new();
+        let count = |object_type: ObjectType| {
+            topology
+                .objects_with_type(&object_type)
+                .map(|objects| objects.len())
+                .unwrap_or(0)
+        };
+        // caches of all levels share the same type so we look at all depths
+        let cache_sizes = |level: u32| {
+            (0..topology.depth())
+                .filter(|&depth| topology.type_at_depth(depth) == ObjectType::Cache)
+                .flat_map(|depth| topology.objects_at_depth(depth))
+                .filter_map(|cache| cache.cache_attributes())
+                .filter(|attributes| attributes.depth() == level)
+                .map(|attributes| attributes.size() as usize)
+                .filter(|&size| size > 0)
+                .collect::<Vec<usize>>()
+        };
+        let l3_cache_size: usize = cache_sizes(3).iter().sum();
+        let cores = count(ObjectType::Core);
+        MachineTopology {
+            cores: if cores == 0 {
+                current_num_threads()
+            } else {
+                cores
+            },
+            numa_nodes: max(count(ObjectType::NUMANode), 1),
+            l2_cache_size: cache_sizes(2).into_iter().min(),
+            l3_cache_size: if l3_cache_size == 0 {
+                None
+            } else {
+                Some(l3_cache_size)
+            },
+        }
+    }
+    /// Return the topology of the machine we run on.
+    /// hwloc is only called once.
+    pub fn current() -> Self {
+        *TOPOLOGY.get_or_init(MachineTopology::load)
+    }
+    /// Size in bytes of the cache each core has for itself.
+    pub fn core_cache_size(&self) -> usize {
+        self.l2_cache_size.unwrap_or(FALLBACK_CORE_CACHE_SIZE)
+    }
+    /// Size in bytes of the cache all threads share.
+    pub fn shared_cache_size(&self) -> usize {
+        self.l3_cache_size
+            .or_else(|| self.l2_cache_size.map(|size| size * self.cores))
+            .unwrap_or(FALLBACK_SHARED_CACHE_SIZE * current_num_threads())
+    }
+}
+
+/// How many elements of type `T` fit in given number of bytes (at least one).
+fn elements_in<T>(bytes: usize) -> usize {
+    max(bytes / max(mem::size_of::<T>(), 1), 1)
+}
+
+/// Largest block of `T` elements one core can process while staying in its own cache.
+pub fn max_block_size<T>() -> usize {
+    elements_in::<T>(MachineTopology::current().core_cache_size())
+}
+
+/// Size of macro-blocks of `T` elements all threads can process while staying in
+/// the shared cache.
+pub fn macro_block_size<T>() -> usize {
+    elements_in::<T>(MachineTopology::current().shared_cache_size())
+}
@@ -0,0 +1,439 @@
+//! Execution traces (`trace` feature).
+//! A `Trace` records timestamped scheduling events for each thread.
+//! It can then be exported as Chrome `trace_event` JSON (for `chrome://tracing` or Perfetto)
+//! or as a standalone svg Gantt chart.
+use crate::activated_input::ActivatedInput;
+use crate::{Divisible, Folder, SchedulingEvent};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+const SVG_WIDTH: f64 = 1920.0;
+const SVG_ROW_HEIGHT: f64 = 30.0;
+const SVG_LEGEND_HEIGHT: f64 = 30.0;
+/// Instantaneous events are drawn as rectangles of this width.
+const SVG_MARK_WIDTH: f64 = 2.0;
+
+/// All kinds of events we record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceEventKind {
+    /// A block of given size got folded.
+    Block(usize),
+    /// Thread stayed blocked, waiting for another one.
+    Wait,
+    /// An idle thread asked for work.
+    StealRequest,
+    /// A working thread gave away some work.
+    StealAnswer,
+    /// A task got executed by another thread than its creator.
+    Steal,
+    /// Input got divided in two.
+    Split,
+    /// Two outputs got reduced.
+    Reduction,
+}
+
+impl TraceEventKind {
+    fn name(&self) -> &'static str {
+        match self {
+            TraceEventKind::Block(_) => "block",
+            TraceEventKind::Wait => "wait",
+            TraceEventKind::StealRequest => "steal request",
+            TraceEventKind::StealAnswer => "steal answer",
+            TraceEventKind::Steal => "steal",
+            TraceEventKind::Split => "split",
+            TraceEventKind::Reduction => "reduction",
+        }
+    }
+    fn color(&self) -> &'static str {
+        match self {
+            TraceEventKind::Block(_) => "#4e79a7",
+            TraceEventKind::Wait => "#e15759",
+            TraceEventKind::StealRequest => "#f28e2b",
+            TraceEventKind::StealAnswer => "#59a14f",
+            TraceEventKind::Steal => "#b07aa1",
+            TraceEventKind::Split => "#76b7b2",
+            TraceEventKind::Reduction => "#edc948",
+        }
+    }
+}
+
+/// One recorded event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEvent {
+    /// Lane of the thread which recorded the event.
+    /// Each thread gets its own lane, numbered in order of first recorded event
+    /// (whatever the pool it belongs to).
+    pub thread: usize,
+    pub kind: TraceEventKind,
+    /// Time elapsed between the trace creation and the event's start.
+    pub start: Duration,
+    /// Zero for instantaneous events.
+    pub duration: Duration,
+}
+
+/// Events of all threads attached to us.
+///
+/// # Example
+///
+/// ```
+/// use rayon_adaptive::prelude::*;
+/// use rayon_adaptive::{Policy, Trace};
+/// let trace = Trace::new();
+/// let sum = (0..100_000)
+///     .into_adapt_iter()
+///     .with_policy(Policy::Adaptive(1_000, 10_000))
+///     .fold(|| 0, |s, e| s + e)
+///     .with_trace(&trace)
+///     .reduce(|s1, s2| s1 + s2);
+/// assert_eq!(sum, 4_999_950_000);
+/// let mut svg = Vec::new();
+/// trace.write_svg(&mut svg).expect("failed writing svg");
+/// let mut json = Vec::new();
+/// trace.write_chrome_trace(&mut json).expect("failed writing json");
+/// ```
+pub struct Trace {
+    start: Instant,
+    lanes: RwLock<Lanes>,
+}
+
+/// One events list per thread, created on demand.
+#[derive(Default)]
+struct Lanes {
+    indices: HashMap<ThreadId, usize>,
+    events: Vec<Mutex<Vec<TraceEvent>>>,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Trace::new()
+    }
+}
+
+impl Trace {
+    /// Create a new trace, for computations on any thread pool.
+    pub fn new() -> Self {
+        Trace {
+            start: Instant::now(),
+            lanes: RwLock::new(Lanes::default()),
+        }
+    }
+    fn add(&self, kind: TraceEventKind, start: Instant, duration: Duration) {
+        let id = thread::current().id();
+        let start = start.saturating_duration_since(self.start);
+        {
+            let lanes = self.lanes.read().expect("poisoned trace");
+            if let Some(&thread) = lanes.indices.get(&id) {
+                lanes.events[thread]
+                    .lock()
+                    .expect("poisoned trace")
+                    .push(TraceEvent {
+                        thread,
+                        kind,
+                        start,
+                        duration,
+                    });
+                return;
+            }
+        }
+        // first event of this thread
+        let mut lanes = self.lanes.write().expect("poisoned trace");
+        let thread = lanes.events.len();
+        lanes.indices.insert(id, thread);
+        lanes.events.push(Mutex::new(vec![TraceEvent {
+            thread,
+            kind,
+            start,
+            duration,
+        }]));
+    }
+    fn record(&self, event: SchedulingEvent) {
+        let now = Instant::now();
+        let (kind, duration) = match event {
+            SchedulingEvent::Split => (TraceEventKind::Split, Duration::default()),
+            SchedulingEvent::Steal => (TraceEventKind::Steal, Duration::default()),
+            SchedulingEvent::StealRequest => (TraceEventKind::StealRequest, Duration::default()),
+            SchedulingEvent::StealAnswer { .. } => {
+                (TraceEventKind::StealAnswer, Duration::default())
+            }
+            SchedulingEvent::Reduction => (TraceEventKind::Reduction, Duration::default()),
+            SchedulingEvent::Wait(duration) => (TraceEventKind::Wait, duration),
+            SchedulingEvent::MasterFold(size, duration) => (TraceEventKind::Block(size), duration),
+        };
+        // events are reported when they end
+        self.add(kind, now - duration, duration)
+    }
+    /// All events recorded so far, sorted by starting times.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        let mut events: Vec<TraceEvent> = self
+            .lanes
+            .read()
+            .expect("poisoned trace")
+            .events
+            .iter()
+            .flat_map(|events| events.lock().expect("poisoned trace").clone())
+            .collect();
+        events.sort_by_key(|e| e.start);
+        events
+    }
+    /// Write all events as Chrome `trace_event` json.
+    pub fn write_chrome_trace<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "[")?;
+        for (index, event) in self.events().iter().enumerate() {
+            if index != 0 {
+                writeln!(writer, ",")?;
+            }
+            write!(
+                writer,
+                "{{\"name\":\"{}\",\"pid\":0,\"tid\":{},\"ts\":{:.3}",
+                event.kind.name(),
+                event.thread,
+                micros(event.start)
+            )?;
+            match event.kind {
+                TraceEventKind::Block(size) => write!(
+                    writer,
+                    ",\"ph\":\"X\",\"dur\":{:.3},\"args\":{{\"size\":{}}}}}",
+                    micros(event.duration),
+                    size
+                )?,
+                TraceEventKind::Wait => write!(
+                    writer,
+                    ",\"ph\":\"X\",\"dur\":{:.3}}}",
+                    micros(event.duration)
+                )?,
+                _ => write!(writer, ",\"ph\":\"i\",\"s\":\"t\"}}")?,
+            }
+        }
+        writeln!(writer, "\n]")?;
+        writer.flush()
+    }
+    /// Save all events as Chrome `trace_event` json in given file.
+    pub fn save_chrome_trace<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_chrome_trace(BufWriter::new(File::create(path)?))
+    }
+    /// Write a Gantt chart of all events as an svg image.
+    /// Each thread gets a row and each kind of event a color.
+    pub fn write_svg<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let events = self.events();
+        let threads = self.lanes.read().expect("poisoned trace").events.len();
+        let end = events
+            .iter()
+            .map(|e| micros(e.start + e.duration))
+            .fold(0.0, f64::max);
+        let x_scale = if end > 0.0 { SVG_WIDTH / end } else { 0.0 };
+        let height = threads as f64 * SVG_ROW_HEIGHT + SVG_LEGEND_HEIGHT;
+        writeln!(
+            writer,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">",
+            SVG_WIDTH, height, SVG_WIDTH, height
+        )?;
+        writeln!(
+            writer,
+            "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>",
+            SVG_WIDTH, height
+        )?;
+        for event in &events {
+            let x = micros(event.start) * x_scale;
+            let width = (micros(event.duration) * x_scale).max(SVG_MARK_WIDTH);
+            // instantaneous events only take the lower half of their row
+            let (y, event_height) = match event.kind {
+                TraceEventKind::Block(_) | TraceEventKind::Wait => {
+                    (event.thread as f64 * SVG_ROW_HEIGHT, SVG_ROW_HEIGHT)
+                }
+                _ => (
+                    (event.thread as f64 + 0.5) * SVG_ROW_HEIGHT,
+                    SVG_ROW_HEIGHT / 2.0,
+                ),
+            };
+            let size = match event.kind {
+                TraceEventKind::Block(size) => format!(" of size {}", size),
+                _ => String::new(),
+            };
+            writeln!(
+                writer,
+                "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"{}\"><title>{}{} on thread {}: {:?}</title></rect>",
+                x,
+                y,
+                width,
+                event_height,
+                event.kind.color(),
+                event.kind.name(),
+                size,
+                event.thread,
+                event.duration
+            )?;
+        }
+        let legend_y = threads as f64 * SVG_ROW_HEIGHT;
+        let kinds = [
+            TraceEventKind::Block(0),
+            TraceEventKind::Wait,
+            TraceEventKind::StealRequest,
+            TraceEventKind::StealAnswer,
+            TraceEventKind::Steal,
+            TraceEventKind::Split,
+            TraceEventKind::Reduction,
+        ];
+        for (index, kind) in kinds.iter().enumerate() {
+            let x = index as f64 * SVG_WIDTH / kinds.len() as f64;
+            writeln!(
+                writer,
+                "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+                x,
+                legend_y + SVG_LEGEND_HEIGHT / 4.0,
+                SVG_LEGEND_HEIGHT / 2.0,
+                SVG_LEGEND_HEIGHT / 2.0,
+                kind.color()
+            )?;
+            writeln!(
+                writer,
+                "<text x=\"{:.2}\" y=\"{:.2}\" font-family=\"sans-serif\" font-size=\"14\">{}</text>",
+                x + SVG_LEGEND_HEIGHT,
+                legend_y + SVG_LEGEND_HEIGHT * 0.7,
+                kind.name()
+            )?;
+        }
+        writeln!(writer, "</svg>")?;
+        writer.flush()
+    }
+    /// Save a Gantt chart of all events as an svg image in given file.
+    pub fn save_svg<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_svg(BufWriter::new(File::create(path)?))
+    }
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1_000_000.0 + f64::from(duration.subsec_nanos()) / 1_000.0
+}
+
+/// Folder recording all blocks and scheduling events in a `Trace`.
+#[must_use = "folders are lazy and do nothing unless consumed"]
+pub struct WithTrace<'a, F> {
+    pub(crate) inner_folder: F,
+    pub(crate) trace: &'a Trace,
+}
+
+impl<'a, F: Folder> Folder for WithTrace<'a, F> {
+    type Input = F::Input;
+    type IntermediateOutput = F::IntermediateOutput;
+    type Output = F::Output;
+    fn identity(&self) -> Self::IntermediateOutput {
+        self.inner_folder.identity()
+    }
+    fn fold(
+        &self,
+        io: Self::IntermediateOutput,
+        i: Self::Input,
+        limit: usize,
+    ) -> (Self::IntermediateOutput, Self::Input) {
+        let initial_length = i.base_length();
+        let start = Instant::now();
+        let (io, remaining) = self.inner_folder.fold(io, i, limit);
+        let size = initial_length.saturating_sub(remaining.base_length());
+        self.trace
+            .add(TraceEventKind::Block(size), start, start.elapsed());
+        (io, remaining)
+    }
+    fn to_output(&self, io: Self::IntermediateOutput, i: Self::Input) -> Self::Output {
+        self.inner_folder.to_output(io, i)
+    }
+    fn processed(&self, size: usize) {
+        // the master's blocks are timed by the scheduler (see `SchedulingEvent::MasterFold`)
+        self.inner_folder.processed(size)
+    }
+    fn record(&self, event: SchedulingEvent) {
+        self.trace.record(event);
+        self.inner_folder.record(event)
+    }
+}
+
+impl<'p, F: Folder, S, P> ActivatedInput<'p, F, S, P> {
+    /// Record all blocks and scheduling events in given trace.
+    pub fn with_trace(self, trace: &Trace) -> ActivatedInput<'p, WithTrace<'_, F>, S, P> {
+        ActivatedInput {
+            input: self.input,
+            folder: WithTrace {
+                inner_folder: self.folder,
+                trace,
+            },
+            policy: self.policy,
+            sizes: self.sizes,
+            max_threads: self.max_threads,
+            pool: self.pool,
+            cache_block_size: self.cache_block_size,
+            power: self.power,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Trace;
+    use crate::prelude::*;
+    use crate::Policy;
+
+    #[test]
+    fn chrome_traces_contain_one_event_per_task() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .expect("pool build failed");
+        let trace = Trace::new();
+        let sum = pool.install(|| {
+            (0..10_000)
+                .into_adapt_iter()
+                .with_policy(Policy::Join(100))
+                .fold(|| 0, |s, e| s + e)
+                .with_trace(&trace)
+                .reduce(|s1, s2| s1 + s2)
+        });
+        assert_eq!(sum, 49_995_000);
+        let mut json = Vec::new();
+        trace
+            .write_chrome_trace(&mut json)
+            .expect("failed writing json");
+        let json: serde_json::Value = serde_json::from_slice(&json).expect("invalid json");
+        let events = json.as_array().expect("trace is not an array");
+        assert_eq!(events.len(), trace.events().len());
+        let named = |name: &'static str| events.iter().filter(move |e| e["name"] == name);
+        // 10_000 elements get divided in 128 tasks of 78 or 79 elements
+        let blocks: Vec<_> = named("block").collect();
+        assert_eq!(blocks.len(), 128);
+        for block in &blocks {
+            assert_eq!(block["ph"], "X");
+            assert!(block["dur"].as_f64().expect("no duration") >= 0.0);
+        }
+        let sizes: Vec<u64> = blocks
+            .iter()
+            .map(|block| block["args"]["size"].as_u64().expect("no size"))
+            .collect();
+        assert!(sizes.iter().all(|&size| size == 78 || size == 79));
+        assert_eq!(sizes.iter().sum::<u64>(), 10_000);
+        assert_eq!(named("split").count(), 127);
+        assert_eq!(named("reduction").count(), 127);
+        assert!(named("steal").count() <= 127);
+        assert_eq!(
+            blocks.len()
+                + named("split").count()
+                + named("reduction").count()
+                + named("steal").count()
+                + named("wait").count(),
+            events.len()
+        );
+        let mut last_start = 0.0;
+        for event in events {
+            assert_eq!(event["pid"], 0);
+            assert!(event["tid"].as_u64().expect("no tid") < 4);
+            if event["ph"] == "i" {
+                assert_eq!(event["s"], "t");
+            }
+            let start = event["ts"].as_f64().expect("no timestamp");
+            assert!(start >= last_start);
+            last_start = start;
+        }
+    }
+}
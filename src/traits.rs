@@ -27,11 +27,13 @@ pub trait Divisible: Sized + Send + Sync {
     /// * TODO: for now we require base_length to be exactly equal to the number of loops
     /// we should remove this constraint
     fn base_length(&self) -> usize;
-    fn with_policy(self, policy: Policy) -> ParametrizedInput<Self, Empty<usize>> {
+    fn with_policy<'p>(self, policy: Policy) -> ParametrizedInput<'p, Self, Empty<usize>> {
         ParametrizedInput {
             input: self,
             policy,
             sizes: empty(),
+            max_threads: None,
+            pool: None,
         }
     }
 }
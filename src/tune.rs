@@ -0,0 +1,193 @@
+//! Automatic tuning of scheduling policies.
+//! `autotune` times an algorithm with several policies and block sizes on a sample input.
+//! The best policy is saved under a name in a local profile file.
+//! `Policy::Tuned(name)` then loads it back.
+use crate::Policy;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::iter::once;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Where we save all tuned policies (in the current directory).
+/// Each line holds a name and a policy (as displayed), separated by a tab.
+const PROFILE_FILE: &str = "rayon_adaptive.profile";
+/// How many times we run each candidate policy (we keep the median time).
+const TUNING_RUNS: usize = 5;
+
+type Profiles = Arc<HashMap<String, Policy>>;
+
+/// Profile's content, loaded on first use.
+static PROFILES: Mutex<Option<Profiles>> = Mutex::new(None);
+/// Incremented each time profiles change.
+static PROFILES_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+// each thread keeps its own copy of the profiles so schedulers never lock.
+thread_local!(static CACHED_PROFILES: RefCell<Option<(usize, Profiles)>> = const { RefCell::new(None) });
+
+/// All block sizes we explore.
+fn block_sizes() -> impl Iterator<Item = usize> + Clone {
+    (3..10).map(|power| 1 << (2 * power))
+}
+
+/// All policies we explore.
+fn candidates() -> impl Iterator<Item = Policy> {
+    let adaptive_sizes = block_sizes().flat_map(|min_size| {
+        block_sizes()
+            .filter(move |&max_size| max_size > min_size)
+            .map(move |max_size| Policy::Adaptive(min_size, max_size))
+    });
+    once(Policy::DefaultPolicy)
+        .chain(once(Policy::Sequential))
+        .chain(once(Policy::Rayon))
+        .chain(block_sizes().map(Policy::Join))
+        .chain(block_sizes().map(Policy::JoinContext))
+        .chain(adaptive_sizes)
+}
+
+/// Return the policy saved under given name or the default policy if none.
+pub(crate) fn tuned_policy(name: &str) -> Policy {
+    let generation = PROFILES_GENERATION.load(Ordering::Acquire);
+    CACHED_PROFILES.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let up_to_date = cache
+            .as_ref()
+            .is_some_and(|(cached_generation, _)| *cached_generation == generation);
+        if !up_to_date {
+            let mut profiles = PROFILES.lock().expect("poisoned profiles");
+            let profiles = profiles.get_or_insert_with(|| Arc::new(load_profiles()));
+            *cache = Some((generation, profiles.clone()));
+        }
+        let (_, profiles) = cache.as_ref().expect("no cached profiles");
+        profiles.get(name).copied().unwrap_or_default()
+    })
+}
+
+fn load_profiles() -> HashMap<String, Policy> {
+    fs::read_to_string(PROFILE_FILE)
+        .map(|content| parse_profiles(&content))
+        .unwrap_or_default()
+}
+
+fn parse_profiles(content: &str) -> HashMap<String, Policy> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(2, '\t');
+            let name = fields.next()?;
+            let policy = fields.next()?.parse().ok()?;
+            match policy {
+                // tuned policies cannot refer to other ones
+                Policy::Tuned(_) => None,
+                _ => Some((name.to_owned(), policy)),
+            }
+        })
+        .collect()
+}
+
+fn save_profiles(profiles: &HashMap<String, Policy>) -> io::Result<()> {
+    let mut file = io::BufWriter::new(fs::File::create(PROFILE_FILE)?);
+    write_profiles(&mut file, profiles)?;
+    file.flush()
+}
+
+fn write_profiles<W: Write>(output: &mut W, profiles: &HashMap<String, Policy>) -> io::Result<()> {
+    let mut names: Vec<&String> = profiles.keys().collect();
+    names.sort();
+    for name in names {
+        writeln!(output, "{}\t{}", name, profiles[name])?;
+    }
+    Ok(())
+}
+
+/// Median time of running the algorithm on the sample with given policy.
+fn median_time<I, R, F>(sample_input: &I, algorithm: &F, policy: Policy) -> Duration
+where
+    I: Clone,
+    F: Fn(I, Policy) -> R,
+{
+    let mut times: Vec<Duration> = (0..TUNING_RUNS)
+        .map(|_| {
+            let input = sample_input.clone();
+            let start = Instant::now();
+            let result = algorithm(input, policy);
+            let time = start.elapsed();
+            drop(result);
+            time
+        })
+        .collect();
+    times.sort();
+    times[TUNING_RUNS / 2]
+}
+
+/// Find the fastest policy for running `algorithm` on inputs like `sample_input`
+/// in the current thread pool.
+/// It gets saved under given name in a profile file of the current directory
+/// so that later runs can use it with `Policy::Tuned(name)`.
+///
+/// # Example
+///
+/// ```no_run
+/// use rayon_adaptive::prelude::*;
+/// use rayon_adaptive::{autotune, Policy};
+/// let v: Vec<u64> = (0..1_000_000).collect();
+/// let sum = |v: &[u64], policy| {
+///     v.into_adapt_iter()
+///         .with_policy(policy)
+///         .fold(|| 0, |s, e| s + e)
+///         .reduce(|a, b| a + b)
+/// };
+/// autotune("sum", v.as_slice(), sum).expect("failed saving profile");
+/// // later on
+/// assert_eq!(sum(&v, Policy::Tuned("sum")), 499_999_500_000);
+/// ```
+pub fn autotune<I, R, F>(name: &str, sample_input: I, algorithm: F) -> io::Result<Policy>
+where
+    I: Clone,
+    F: Fn(I, Policy) -> R,
+{
+    let best_policy = candidates()
+        .map(|policy| (median_time(&sample_input, &algorithm, policy), policy))
+        .min_by_key(|&(time, _)| time)
+        .map(|(_, policy)| policy)
+        .unwrap_or_default();
+    let mut profiles = PROFILES.lock().expect("poisoned profiles");
+    // re-read the file in case another process updated it
+    let mut updated_profiles = load_profiles();
+    updated_profiles.insert(name.to_owned(), best_policy);
+    save_profiles(&updated_profiles)?;
+    *profiles = Some(Arc::new(updated_profiles));
+    PROFILES_GENERATION.fetch_add(1, Ordering::Release);
+    Ok(best_policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{candidates, parse_profiles, write_profiles};
+    use crate::Policy;
+    use std::collections::HashMap;
+
+    #[test]
+    fn profiles_round_trip() {
+        let profiles: HashMap<String, Policy> = candidates()
+            .chain(vec![Policy::DepJoin(100), Policy::Numa(10, 1000)])
+            .enumerate()
+            .map(|(index, policy)| (format!("algorithm {}", index), policy))
+            .collect();
+        let mut saved = Vec::new();
+        write_profiles(&mut saved, &profiles).expect("failed writing profiles");
+        let saved = String::from_utf8(saved).expect("profiles are not utf8");
+        let loaded = parse_profiles(&saved);
+        assert_eq!(loaded.len(), profiles.len());
+        for (name, policy) in &profiles {
+            assert_eq!(loaded[name].to_string(), policy.to_string());
+        }
+        // invalid lines and tuned policies get skipped
+        let loaded = parse_profiles("sum\tjoin:100\nno policy\nbad\tjoin:x\nloop\ttuned:sum\n");
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded["sum"].to_string(), "join:100");
+    }
+}